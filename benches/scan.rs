@@ -0,0 +1,104 @@
+//! Times scanning a tree of a million small files with different numbers of threads. The tree is
+//! created in the target directory the first time this runs, which takes a while. Run with
+//! `cargo bench`, setting `RSDU_BENCH_FILES` to use a different number of files.
+//!
+//! Setting `RSDU_BENCH_BASELINE` to an rsdu binary built from before the scan used a thread pool,
+//! which spawned a thread per directory entry instead, times that too and compares each thread
+//! count against it rather than against a single thread.
+use std::{
+    env, fs,
+    path::Path,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+/// The number of entries in each directory of the generated tree.
+const FANOUT: usize = 100;
+/// How many times each scan is repeated, keeping the fastest.
+const RUNS: usize = 3;
+
+fn main() {
+    let files = env::var("RSDU_BENCH_FILES")
+        .ok()
+        .and_then(|files| files.parse().ok())
+        .unwrap_or(1_000_000);
+    // The binary is in a profile's folder of the target directory, wherever that is
+    let target = Path::new(env!("CARGO_BIN_EXE_rsdu"))
+        .parent()
+        .and_then(Path::parent)
+        .unwrap();
+    let root = target.join(format!("bench-tree-{}", files));
+    if !root.exists() {
+        let partial = root.with_extension("partial");
+        let _ = fs::remove_dir_all(&partial);
+        eprintln!("creating {} files in {}", files, root.display());
+        create_tree(&partial, files).unwrap();
+        fs::rename(&partial, &root).unwrap();
+    }
+
+    let mut thread_counts = vec![1, 2, 4, 8, num_cpus::get()];
+    thread_counts.sort_unstable();
+    thread_counts.dedup();
+    // Warm the inode and dentry caches, so that every run is measured the same way
+    scan(Path::new(env!("CARGO_BIN_EXE_rsdu")), &root, Some(1));
+    let mut baseline = env::var_os("RSDU_BENCH_BASELINE").map(|binary| {
+        let time = (0..RUNS)
+            .map(|_| scan(Path::new(&binary), &root, None))
+            .min()
+            .unwrap();
+        println!("  baseline: {:>8.3}s", time.as_secs_f64());
+        time
+    });
+    for threads in thread_counts {
+        let time = (0..RUNS)
+            .map(|_| scan(Path::new(env!("CARGO_BIN_EXE_rsdu")), &root, Some(threads)))
+            .min()
+            .unwrap();
+        let baseline = *baseline.get_or_insert(time);
+        println!(
+            "{:>2} threads: {:>8.3}s ({:.2}x)",
+            threads,
+            time.as_secs_f64(),
+            baseline.as_secs_f64() / time.as_secs_f64()
+        );
+    }
+}
+
+/// Creates `files` one byte files under `dir`, spread over directories of [`FANOUT`] entries.
+fn create_tree(dir: &Path, files: usize) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    if files <= FANOUT {
+        for i in 0..files {
+            fs::write(dir.join(i.to_string()), b"x")?;
+        }
+        return Ok(());
+    }
+    let per_subdir = files.div_ceil(FANOUT);
+    let mut remaining = files;
+    for i in 0.. {
+        if remaining == 0 {
+            break;
+        }
+        let count = per_subdir.min(remaining);
+        create_tree(&dir.join(i.to_string()), count)?;
+        remaining -= count;
+    }
+    Ok(())
+}
+
+/// Times `binary` scanning `root`, with `threads` if it takes the option.
+fn scan(binary: &Path, root: &Path, threads: Option<usize>) -> Duration {
+    let mut command = Command::new(binary);
+    command.args(["--report", "--depth", "0"].iter());
+    if let Some(threads) = threads {
+        command.arg("--threads").arg(threads.to_string());
+    }
+    let start = Instant::now();
+    let status = command
+        .arg(root)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    start.elapsed()
+}
@@ -0,0 +1,261 @@
+//! Finding entries anywhere in the scanned tree by glob or regex, or by size.
+use crate::{
+    join_path_to_vec, pad_and_prettify_bytes, prettify_bytes, Excludes, PathInfo, Size, SizeMode,
+};
+use regex_automata::meta::Regex;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, BinaryHeap},
+    ffi::OsString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, List, ListItem, ListState},
+    Frame,
+};
+
+/// What the entries listed by the found view were found by.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// A glob or regex, as it was entered.
+    Pattern(String),
+    /// The `count` largest files of at least `min_size` bytes.
+    Largest { count: usize, min_size: u64 },
+}
+
+/// Entries found anywhere in the tree.
+pub struct Found {
+    pub query: Query,
+    /// The found paths, in the order they appear in the tree.
+    pub paths: Vec<Vec<OsString>>,
+}
+
+impl Found {
+    /// Finds the `count` largest files in `root` of at least `min_size` bytes.
+    pub fn largest(root: &PathInfo, mode: SizeMode, count: usize, min_size: u64) -> Found {
+        Found {
+            query: Query::Largest { count, min_size },
+            paths: largest(root, mode, count, min_size),
+        }
+    }
+
+    fn describe(&self, found: usize) -> String {
+        match &self.query {
+            Query::Pattern(pattern) => format!("{} matching {}", found, pattern),
+            Query::Largest { min_size: 0, .. } => format!("{} largest files", found),
+            Query::Largest { min_size, .. } => format!(
+                "{} largest files of at least {}",
+                found,
+                prettify_bytes(min_size)
+            ),
+        }
+    }
+
+    /// The matching entries still in `root`, largest first.
+    pub fn entries<'a>(
+        &'a self,
+        root: &'a PathInfo,
+        mode: SizeMode,
+    ) -> Vec<(&'a Vec<OsString>, &'a PathInfo)> {
+        let mut entries: Vec<_> = self
+            .paths
+            .iter()
+            .filter_map(|path| Some((path, root.get(path)?)))
+            .collect();
+        entries.sort_by_key(|(_, info)| Reverse(info.size(mode)));
+        entries
+    }
+
+    /// The combined size of the matching entries still in `root`, counting matches inside
+    /// other matching folders only once.
+    fn total(&self, root: &PathInfo) -> Size {
+        let mut total = Size::default();
+        let mut outer: Option<&Vec<OsString>> = None;
+        // Every path comes right after its ancestors
+        for path in &self.paths {
+            if outer.is_some_and(|outer| path.starts_with(outer)) {
+                continue;
+            }
+            if let Some(info) = root.get(path) {
+                total += info.contribution();
+                outer = Some(path);
+            }
+        }
+        total
+    }
+
+    /// Draws the matching entries still in `root` in `area`, largest first, with the cursor at
+    /// `state` and the entries in `marked` highlighted.
+    pub fn draw<B: Backend>(
+        &self,
+        f: &mut Frame<B>,
+        area: Rect,
+        root: &PathInfo,
+        mode: SizeMode,
+        marked: &BTreeSet<Vec<OsString>>,
+        state: &mut ListState,
+    ) {
+        let entries = self.entries(root, mode);
+        let items: Vec<ListItem> = entries
+            .iter()
+            .map(|(path, info)| {
+                let mark = if marked.contains(*path) { '*' } else { ' ' };
+                let item = ListItem::new(Spans::from(Span::raw(
+                    mark.to_string()
+                        + &info.flag().to_string()
+                        + &pad_and_prettify_bytes(&info.size(mode))
+                        + "  "
+                        + &join_path_to_vec(Path::new(""), (*path).clone()).to_string_lossy()
+                        + &info.suffix(),
+                )));
+                if mark == '*' {
+                    item.style(Style::default().fg(Color::Yellow))
+                } else {
+                    item
+                }
+            })
+            .collect();
+        let paths = List::new(items)
+            .block(
+                Block::default()
+                    .title(format!(
+                        " {}, {} in total (enter: go to folder{}) ",
+                        self.describe(entries.len()),
+                        prettify_bytes(&self.total(root).get(mode)),
+                        match self.query {
+                            Query::Pattern(..) => "",
+                            Query::Largest { .. } => ", +/-: minimum size",
+                        }
+                    ))
+                    .borders(Borders::ALL),
+            )
+            .highlight_style(
+                Style::default()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::BOLD),
+            );
+        if let Some(selected) = state.selected() {
+            state.select(Some(selected.min(entries.len().saturating_sub(1))));
+        }
+        f.render_stateful_widget(paths, area, state);
+    }
+}
+
+/// What entries are found by.
+pub enum Pattern {
+    /// Globs, which work just like `--exclude` patterns.
+    Glob(Excludes),
+    /// A regex searched for in the name, or in the full path if the regex contains a `/`.
+    Regex { regex: Regex, whole_path: bool },
+}
+
+impl Pattern {
+    /// Parses `text` as a regex if it starts with `re:`, or as a glob otherwise.
+    pub fn new(text: &str) -> Result<Pattern, String> {
+        match text.strip_prefix("re:") {
+            Some(regex) => Ok(Pattern::Regex {
+                regex: Regex::new(regex).map_err(|e| match e.syntax_error() {
+                    // The last line says what's wrong, below a drawing of where
+                    Some(e) => e.to_string().lines().last().unwrap_or_default().to_string(),
+                    None => e.to_string(),
+                })?,
+                whole_path: regex.contains('/'),
+            }),
+            None => Ok(Pattern::Glob(
+                Excludes::new(&[text.to_string()]).map_err(|e| e.to_string())?,
+            )),
+        }
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        match self {
+            Pattern::Glob(globs) => globs.is_match(path),
+            Pattern::Regex {
+                regex,
+                whole_path: true,
+            } => regex.is_match(path.as_os_str().as_bytes()),
+            Pattern::Regex { regex, .. } => path
+                .file_name()
+                .is_some_and(|name| regex.is_match(name.as_bytes())),
+        }
+    }
+}
+
+/// Collects every entry below `info` matching `pattern`, where `info` is found at `path` on disk
+/// and at `vec` in the tree.
+pub fn find(
+    info: &PathInfo,
+    path: &mut PathBuf,
+    vec: &mut Vec<OsString>,
+    pattern: &Pattern,
+    found: &mut Vec<Vec<OsString>>,
+) {
+    if let Ok(contents) = info.contents() {
+        for (name, child) in contents {
+            path.push(name);
+            vec.push(name.clone());
+            if pattern.is_match(path) {
+                found.push(vec.clone());
+            }
+            find(child, path, vec, pattern, found);
+            vec.pop();
+            path.pop();
+        }
+    }
+}
+
+/// The paths of the `count` largest files in `root` of at least `min_size` bytes, in the order
+/// they appear in the tree. Duplicate hard links are left out, since they take no extra space.
+pub fn largest(root: &PathInfo, mode: SizeMode, count: usize, min_size: u64) -> Vec<Vec<OsString>> {
+    let mut heap = BinaryHeap::new();
+    if count > 0 {
+        collect_largest(root, &mut vec![], mode, count, min_size, &mut heap);
+    }
+    let mut paths: Vec<_> = heap.into_iter().map(|Reverse((_, path))| path).collect();
+    paths.sort();
+    paths
+}
+
+/// Adds the files below `info`, which is at `vec` in the tree, to `heap` when they're larger than
+/// the smallest file in it, keeping it to `count` files.
+fn collect_largest(
+    info: &PathInfo,
+    vec: &mut Vec<OsString>,
+    mode: SizeMode,
+    count: usize,
+    min_size: u64,
+    heap: &mut BinaryHeap<Reverse<(u64, Vec<OsString>)>>,
+) {
+    match info {
+        PathInfo::Folder { contents, .. } => {
+            for (name, child) in contents {
+                vec.push(name.clone());
+                collect_largest(child, vec, mode, count, min_size, heap);
+                vec.pop();
+            }
+        }
+        PathInfo::File {
+            duplicate: false, ..
+        } => {
+            let size = info.size(mode);
+            if size < min_size {
+                return;
+            }
+            if heap.len() < count {
+                heap.push(Reverse((size, vec.clone())));
+            } else if heap
+                .peek()
+                .is_some_and(|Reverse((smallest, _))| size > *smallest)
+            {
+                heap.pop();
+                heap.push(Reverse((size, vec.clone())));
+            }
+        }
+        _ => {}
+    }
+}
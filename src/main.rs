@@ -1,16 +1,35 @@
-// TODO: Catch errors when scanning and display them to the user, then continue
-// TODO: Display scanning animation when refreshing too
-// TODO: Allow specifying a command to print the size of a file instead of using disk usage
-// TODO: Add an argument parser to handle invalid input better
+mod find;
+mod ncdu;
+mod report;
+mod search;
+mod sort;
+mod stats;
+mod trash;
+mod watch;
+
+use clap::{CommandFactory, Parser};
+use find::{Found, Query};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use search::Search;
+use sort::{extension, natural_cmp, Sort, SortKey};
+use stats::StatsView;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     env,
     ffi::OsString,
-    fs, io,
+    fs,
+    io::{self, Read},
     iter::FromIterator,
+    ops::{Add, AddAssign, Sub},
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
     thread,
+    time::{Duration, Instant, SystemTime},
 };
 use termion::{
     event::Key,
@@ -19,455 +38,3302 @@ use termion::{
     screen::AlternateScreen,
 };
 use tui::{
-    backend::TermionBackend,
-    layout::{Alignment, Constraint, Direction, Layout},
+    backend::{Backend, TermionBackend},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
-    Terminal,
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
 };
 
-#[derive(Debug)]
+/// Frames of the animation shown in place of the flag of folders that are still being scanned.
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Which of the two sizes tracked for every entry is displayed and sorted by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SizeMode {
+    /// The space actually allocated on disk, like `du` and `ncdu` report by default.
+    Disk,
+    /// The length of the file's contents, which can be larger than its disk usage for sparse
+    /// files or smaller for tiny files occupying a whole block.
+    Apparent,
+}
+
+impl SizeMode {
+    fn toggled(self) -> SizeMode {
+        match self {
+            SizeMode::Disk => SizeMode::Apparent,
+            SizeMode::Apparent => SizeMode::Disk,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SizeMode::Disk => "Total disk usage",
+            SizeMode::Apparent => "Total apparent size",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Size {
+    apparent: u64,
+    disk: u64,
+}
+
+impl Size {
+    fn get(self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Disk => self.disk,
+            SizeMode::Apparent => self.apparent,
+        }
+    }
+}
+
+impl From<&fs::Metadata> for Size {
+    fn from(metadata: &fs::Metadata) -> Size {
+        Size {
+            apparent: metadata.len(),
+            // `st_blocks` is always in 512-byte units, regardless of the filesystem's block size
+            disk: metadata.blocks() * 512,
+        }
+    }
+}
+
+impl Add for Size {
+    type Output = Size;
+
+    fn add(self, other: Size) -> Size {
+        Size {
+            apparent: self.apparent + other.apparent,
+            disk: self.disk + other.disk,
+        }
+    }
+}
+
+impl AddAssign for Size {
+    fn add_assign(&mut self, other: Size) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Size {
+    type Output = Size;
+
+    fn sub(self, other: Size) -> Size {
+        Size {
+            apparent: self.apparent.saturating_sub(other.apparent),
+            disk: self.disk.saturating_sub(other.disk),
+        }
+    }
+}
+
+/// The number of files and folders in a tree, where anything that isn't a folder counts as a
+/// file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Count {
+    files: u64,
+    dirs: u64,
+}
+
+impl Count {
+    fn total(self) -> u64 {
+        self.files + self.dirs
+    }
+}
+
+impl Add for Count {
+    type Output = Count;
+
+    fn add(self, other: Count) -> Count {
+        Count {
+            files: self.files + other.files,
+            dirs: self.dirs + other.dirs,
+        }
+    }
+}
+
+impl AddAssign for Count {
+    fn add_assign(&mut self, other: Count) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Count {
+    type Output = Count;
+
+    fn sub(self, other: Count) -> Count {
+        Count {
+            files: self.files.saturating_sub(other.files),
+            dirs: self.dirs.saturating_sub(other.dirs),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
 enum PathInfo {
-    File(u64),
-    Folder(u64, BTreeMap<OsString, PathInfo>, usize),
+    File {
+        size: Size,
+        /// The number of hard links to this file.
+        links: u64,
+        ino: u64,
+        /// The last modification time, in seconds since the epoch.
+        mtime: i64,
+        /// Whether another link to this file was already counted elsewhere in the scan, in
+        /// which case this entry contributes nothing to the size of its ancestors.
+        duplicate: bool,
+    },
+    Folder {
+        size: Size,
+        /// The number of entries anywhere below this folder, like its size not including
+        /// duplicate hard links.
+        count: Count,
+        contents: BTreeMap<OsString, PathInfo>,
+        selected: usize,
+        /// The device containing this folder.
+        dev: u64,
+        /// The latest modification time of this folder or anything below it, in seconds since
+        /// the epoch. It isn't lowered when entries are removed.
+        mtime: i64,
+        /// The error encountered while reading this folder's entries, if any.
+        error: Option<io::ErrorKind>,
+        /// Whether an error was encountered anywhere below this folder.
+        incomplete: bool,
+        /// Whether this folder's entries are still being added by a running scan.
+        scanning: bool,
+    },
+    /// An entry whose metadata could not be read.
+    Error(io::ErrorKind),
+    /// An entry that was deliberately not scanned.
+    Skipped { reason: SkipReason, dir: bool },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SkipReason {
+    /// The directory is a mount point for a different filesystem than the one being scanned.
+    OtherFilesystem,
+    /// The directory is a mount point for a pseudo filesystem like `/proc` or `/sys`.
+    KernelFilesystem,
+    /// The entry matched one of the `--exclude` patterns.
+    Pattern,
+    /// The directory contains a `CACHEDIR.TAG` file.
+    CacheDir,
+}
+
+impl SkipReason {
+    /// Whether entries skipped for this reason were excluded by the user, in which case they
+    /// aren't given a size at all rather than being displayed as empty.
+    fn is_exclusion(self) -> bool {
+        matches!(self, SkipReason::Pattern | SkipReason::CacheDir)
+    }
+}
+
+/// The hard links to one file found in part of the tree.
+#[derive(Debug, Default, PartialEq)]
+struct Links {
+    /// How many links were found.
+    found: u64,
+    /// Whether the link counted in the sizes of its ancestors is among them.
+    counted: bool,
+    /// The size of the file, and its number of links on disk when it was scanned.
+    size: Size,
+    links: u64,
+    /// Whether the file is still linked from more than one place, set by [`PathInfo::relink`].
+    /// Otherwise it needn't be remembered as counted anymore.
+    shared: bool,
+}
+
+impl PathInfo {
+    fn new_folder(
+        own_size: Size,
+        own_mtime: i64,
+        dev: u64,
+        contents: BTreeMap<OsString, PathInfo>,
+        error: Option<io::ErrorKind>,
+    ) -> PathInfo {
+        PathInfo::Folder {
+            dev,
+            mtime: contents
+                .values()
+                .map(PathInfo::mtime)
+                .fold(own_mtime, i64::max),
+            size: own_size + sum_contents(&contents),
+            count: count_contents(contents.values()),
+            incomplete: contents.values().any(PathInfo::has_errors),
+            contents,
+            selected: 0,
+            error,
+            scanning: false,
+        }
+    }
+
+    /// An empty folder whose entries will be added by [`PathInfo::insert`] as it's scanned.
+    fn new_scanning_folder(own_size: Size, mtime: i64, dev: u64) -> PathInfo {
+        PathInfo::Folder {
+            dev,
+            mtime,
+            size: own_size,
+            count: Count::default(),
+            incomplete: false,
+            contents: BTreeMap::new(),
+            selected: 0,
+            error: None,
+            scanning: true,
+        }
+    }
+
+    fn is_scanning(&self) -> bool {
+        matches!(self, PathInfo::Folder { scanning: true, .. })
+    }
+
+    /// Whether an error was encountered reading this entry or anything below it.
+    fn has_errors(&self) -> bool {
+        match self {
+            PathInfo::Folder {
+                error, incomplete, ..
+            } => error.is_some() || *incomplete,
+            PathInfo::File { .. } | PathInfo::Skipped { .. } => false,
+            PathInfo::Error(..) => true,
+        }
+    }
+
+    fn size(&self, mode: SizeMode) -> u64 {
+        match *self {
+            PathInfo::Folder { size, .. } => size.get(mode),
+            PathInfo::File { size, .. } => size.get(mode),
+            PathInfo::Error(..) | PathInfo::Skipped { .. } => 0,
+        }
+    }
+
+    /// The last modification time of this entry, or of anything below it for folders, or 0 if
+    /// it's unknown.
+    fn mtime(&self) -> i64 {
+        match *self {
+            PathInfo::Folder { mtime, .. } | PathInfo::File { mtime, .. } => mtime,
+            PathInfo::Error(..) | PathInfo::Skipped { .. } => 0,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(
+            self,
+            PathInfo::Folder { .. } | PathInfo::Skipped { dir: true, .. }
+        )
+    }
+
+    /// A single character summarizing the state of this entry, shown before its size.
+    fn flag(&self) -> char {
+        match self {
+            PathInfo::Folder { error: Some(_), .. } | PathInfo::Error(..) => '!',
+            PathInfo::Folder {
+                incomplete: true, ..
+            } => '.',
+            PathInfo::File {
+                duplicate: true, ..
+            } => 'H',
+            PathInfo::Skipped { reason, .. } => match reason {
+                SkipReason::OtherFilesystem => '>',
+                SkipReason::KernelFilesystem => '^',
+                SkipReason::Pattern | SkipReason::CacheDir => '<',
+            },
+            _ => ' ',
+        }
+    }
+
+    /// Text appended to this entry's name when it's displayed.
+    fn suffix(&self) -> String {
+        match self {
+            PathInfo::Folder { .. } | PathInfo::Skipped { dir: true, .. } => "/".to_string(),
+            PathInfo::File {
+                links,
+                duplicate: true,
+                ..
+            } => format!(" ({} links)", links),
+            _ => "".to_string(),
+        }
+    }
+
+    fn join(&mut self, vec: &[OsString]) -> Result<&mut PathInfo, io::Error> {
+        let mut curr_res = self;
+        for comp in vec {
+            match curr_res {
+                PathInfo::Folder {
+                    ref mut contents, ..
+                } => match contents.get_mut(comp) {
+                    Some(info) => curr_res = info,
+                    None => return Err(io::Error::other("")),
+                },
+                _ => return Err(io::Error::other("")),
+            };
+        }
+        match curr_res {
+            PathInfo::Folder { .. } => Ok(curr_res),
+            _ => Err(io::Error::other("")),
+        }
+    }
+
+    /// The entry at `vec` relative to this one, which unlike [`PathInfo::join`] needn't be a
+    /// folder.
+    fn get(&self, vec: &[OsString]) -> Option<&PathInfo> {
+        let mut curr_res = self;
+        for comp in vec {
+            curr_res = curr_res.contents().ok()?.get(comp)?;
+        }
+        Some(curr_res)
+    }
+
+    fn contents(&self) -> Result<&BTreeMap<OsString, PathInfo>, io::Error> {
+        match self {
+            PathInfo::Folder { contents, .. } => Ok(contents),
+            _ => Err(io::Error::other("")),
+        }
+    }
+
+    fn sorted(&self, mode: SizeMode, sort: Sort) -> Result<Vec<(&OsString, &PathInfo)>, io::Error> {
+        match self {
+            PathInfo::Folder { contents, .. } => {
+                let mut contents_vec = Vec::from_iter(contents.iter());
+                // Reversing before and after the stable sort keeps entries that compare equal in
+                // the order of their names either way
+                if sort.descending {
+                    contents_vec.reverse();
+                }
+                match sort.key {
+                    SortKey::Size => contents_vec.sort_by_key(|(_, info)| info.size(mode)),
+                    SortKey::Name => contents_vec.sort_by(|(a, _), (b, _)| natural_cmp(a, b)),
+                    SortKey::Count => contents_vec.sort_by_key(|(_, info)| info.count().total()),
+                    SortKey::Mtime => contents_vec.sort_by_key(|(_, info)| info.mtime()),
+                    SortKey::Extension => contents_vec.sort_by(|(a, _), (b, _)| {
+                        extension(a)
+                            .cmp(&extension(b))
+                            .then_with(|| natural_cmp(a, b))
+                    }),
+                }
+                if sort.descending {
+                    contents_vec.reverse();
+                }
+                if sort.dirs_first {
+                    contents_vec.sort_by_key(|(_, info)| !info.is_dir());
+                }
+                Ok(contents_vec)
+            }
+            _ => Err(io::Error::other("")),
+        }
+    }
+
+    /// Collects the `(st_dev, st_ino)` pairs of the hard-linked files counted anywhere below this
+    /// folder.
+    fn counted_links(&self, links: &mut Vec<(u64, u64)>) {
+        if let PathInfo::Folder { contents, dev, .. } = self {
+            for info in contents.values() {
+                match info {
+                    PathInfo::File {
+                        links: count,
+                        ino,
+                        duplicate: false,
+                        ..
+                    } if *count > 1 => links.push((*dev, *ino)),
+                    PathInfo::Folder { .. } => info.counted_links(links),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Collects the hard links at or below this entry, which is in a folder on `dev`, by their
+    /// `(st_dev, st_ino)` pairs.
+    fn hard_links(&self, dev: u64, found: &mut HashMap<(u64, u64), Links>) {
+        match self {
+            PathInfo::File {
+                size,
+                links,
+                ino,
+                duplicate,
+                ..
+            } if *links > 1 => {
+                let entry = found.entry((dev, *ino)).or_default();
+                entry.found += 1;
+                entry.counted |= !duplicate;
+                entry.size = *size;
+                entry.links = *links;
+            }
+            PathInfo::Folder { contents, dev, .. } => {
+                for info in contents.values() {
+                    info.hard_links(*dev, found);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Updates the links left below this folder to files that lost the links in `removed`. The
+    /// first one found of a file whose counted link was removed is counted instead, growing the
+    /// sizes of its ancestors. Returns the size and count added below this folder.
+    fn relink(&mut self, removed: &mut HashMap<(u64, u64), Links>) -> (Size, Count) {
+        let mut added = (Size::default(), Count::default());
+        if let PathInfo::Folder {
+            size,
+            count,
+            contents,
+            dev,
+            ..
+        } = self
+        {
+            for info in contents.values_mut() {
+                match info {
+                    PathInfo::File {
+                        size,
+                        links,
+                        ino,
+                        duplicate,
+                        ..
+                    } => {
+                        if let Some(lost) = removed.get_mut(&(*dev, *ino)) {
+                            *links = links.saturating_sub(lost.found).max(1);
+                            lost.shared = *links > 1;
+                            if lost.counted && *duplicate {
+                                lost.counted = false;
+                                *duplicate = false;
+                                added.0 += *size;
+                                added.1 += Count { files: 1, dirs: 0 };
+                            }
+                        }
+                    }
+                    PathInfo::Folder { .. } => {
+                        let (child_size, child_count) = info.relink(removed);
+                        added.0 += child_size;
+                        added.1 += child_count;
+                    }
+                    _ => {}
+                }
+            }
+            *size += added.0;
+            *count += added.1;
+        }
+        added
+    }
+
+    /// The amount this entry adds to the size of the folder containing it.
+    fn contribution(&self) -> Size {
+        match *self {
+            PathInfo::Folder { size, .. }
+            | PathInfo::File {
+                size,
+                duplicate: false,
+                ..
+            } => size,
+            _ => Size::default(),
+        }
+    }
+
+    /// The number of entries this adds to the count of the folder containing it, including
+    /// itself.
+    fn count(&self) -> Count {
+        match *self {
+            PathInfo::Folder { count, .. } => count + Count { files: 0, dirs: 1 },
+            PathInfo::File {
+                duplicate: true, ..
+            } => Count::default(),
+            PathInfo::Skipped { dir: true, .. } => Count { files: 0, dirs: 1 },
+            _ => Count { files: 1, dirs: 0 },
+        }
+    }
+
+    /// Adds `entries` to the folder at `vec`, growing the size of this folder and every folder
+    /// along `vec` to match.
+    fn insert(&mut self, vec: &[OsString], entries: Vec<(OsString, PathInfo)>) {
+        let added = entries
+            .iter()
+            .fold(Size::default(), |acc, (_, info)| acc + info.contribution());
+        let added_count = count_contents(entries.iter().map(|(_, info)| info));
+        let errors = entries.iter().any(|(_, info)| info.has_errors());
+        let latest = entries.iter().map(|(_, info)| info.mtime()).max();
+        let mut curr_res = self;
+        for comp in vec {
+            match curr_res {
+                PathInfo::Folder {
+                    size,
+                    count,
+                    incomplete,
+                    contents,
+                    mtime,
+                    ..
+                } => {
+                    *size += added;
+                    *count += added_count;
+                    *incomplete |= errors;
+                    *mtime = (*mtime).max(latest.unwrap_or(*mtime));
+                    match contents.get_mut(comp) {
+                        Some(info) => curr_res = info,
+                        None => return,
+                    }
+                }
+                _ => return,
+            }
+        }
+        if let PathInfo::Folder {
+            size,
+            count,
+            incomplete,
+            contents,
+            mtime,
+            ..
+        } = curr_res
+        {
+            *size += added;
+            *count += added_count;
+            *incomplete |= errors;
+            *mtime = (*mtime).max(latest.unwrap_or(*mtime));
+            contents.extend(entries);
+        }
+    }
+
+    /// Marks the folder at `vec` as completely scanned, recording the error encountered while
+    /// reading it, if any.
+    fn finish(&mut self, vec: &[OsString], error: Option<io::ErrorKind>) {
+        if let PathInfo::Folder {
+            contents,
+            incomplete,
+            error: own_error,
+            scanning,
+            ..
+        } = self
+        {
+            match vec.split_first() {
+                Some((first, rest)) => {
+                    *incomplete |= error.is_some();
+                    if let Some(child) = contents.get_mut(first) {
+                        child.finish(rest, error);
+                    }
+                }
+                None => {
+                    *own_error = error;
+                    *scanning = false;
+                }
+            }
+        }
+    }
+
+    /// Replaces the folder at `vec` with `folder`, a new scan of it, updating the sizes and
+    /// error flags of every folder along `vec` to match. The old folder's own size and selection
+    /// are kept, since a scan doesn't count the directory it starts from.
+    fn replace(&mut self, vec: &[OsString], mut folder: PathInfo) {
+        match vec.split_first() {
+            None => {
+                if let (
+                    PathInfo::Folder {
+                        size: old_size,
+                        contents: old_contents,
+                        selected: old_selected,
+                        mtime: old_mtime,
+                        ..
+                    },
+                    PathInfo::Folder {
+                        size,
+                        selected,
+                        mtime,
+                        ..
+                    },
+                ) = (&*self, &mut folder)
+                {
+                    *size += *old_size - sum_contents(old_contents);
+                    *selected = *old_selected;
+                    *mtime = (*mtime).max(*old_mtime);
+                }
+                *self = folder;
+            }
+            Some((first, rest)) => {
+                if let PathInfo::Folder {
+                    size,
+                    count,
+                    contents,
+                    incomplete,
+                    mtime,
+                    ..
+                } = self
+                {
+                    if let Some(child) = contents.get_mut(first) {
+                        let (old, old_count) = (child.contribution(), child.count());
+                        child.replace(rest, folder);
+                        *size = *size - old + child.contribution();
+                        *count = *count - old_count + child.count();
+                        *mtime = (*mtime).max(child.mtime());
+                    }
+                    *incomplete = contents.values().any(PathInfo::has_errors);
+                }
+            }
+        }
+    }
+
+    /// Subtracts `freed` from the size of this folder and every folder along `vec`, and
+    /// `removed` from their counts.
+    fn shrink(&mut self, vec: &[OsString], freed: Size, removed: Count) {
+        if let PathInfo::Folder {
+            size,
+            count,
+            contents,
+            ..
+        } = self
+        {
+            *size = *size - freed;
+            *count = *count - removed;
+            if let Some((first, rest)) = vec.split_first() {
+                if let Some(info) = contents.get_mut(first) {
+                    info.shrink(rest, freed, removed);
+                }
+            }
+        }
+    }
+
+    /// Deletes this entry, located at `path`, from disk. Folders are deleted using their scanned
+    /// contents rather than by re-reading them, so anything created since the scan causes the
+    /// deletion of that folder to fail. Entries that couldn't be deleted are kept, with their
+    /// errors added to `errors`. Returns the size freed and whether this entry was removed
+    /// entirely.
+    fn delete(&mut self, path: &Path, errors: &mut Vec<(io::ErrorKind, PathBuf)>) -> (Size, bool) {
+        let result = match self {
+            PathInfo::Folder {
+                size,
+                count,
+                contents,
+                ..
+            } => {
+                let mut freed = Size::default();
+                contents.retain(|name, info| {
+                    let (child_freed, removed) = info.delete(&path.join(name), errors);
+                    freed += child_freed;
+                    !removed
+                });
+                *size = *size - freed;
+                *count = count_contents(contents.values());
+                match fs::remove_dir(path) {
+                    Ok(()) => return (freed + *size, true),
+                    Err(e) => Err((e, freed)),
+                }
+            }
+            PathInfo::Skipped { dir: true, .. } => {
+                fs::remove_dir(path).map_err(|e| (e, Size::default()))
+            }
+            _ => fs::remove_file(path).map_err(|e| (e, Size::default())),
+        };
+        match result {
+            Ok(()) => (self.contribution(), true),
+            Err((e, freed)) => {
+                errors.push((e.kind(), path.to_path_buf()));
+                (freed, false)
+            }
+        }
+    }
+
+    /// Calls `remove` on the entry named `name` in the folder at `dir`, which returns the size it
+    /// freed and whether the entry is gone, then updates the tree and the sizes of its ancestors
+    /// to match. Another link to each hard-linked file whose counted link was removed is counted
+    /// instead. Returns the `(st_dev, st_ino)` pairs of the files that aren't hard-linked anymore.
+    fn remove_entry(
+        &mut self,
+        dir: &[OsString],
+        name: &OsString,
+        remove: impl FnOnce(&mut PathInfo) -> (Size, bool),
+    ) -> Vec<(u64, u64)> {
+        let mut unlinked = HashMap::new();
+        let (freed, removed_count) = match self.join(dir) {
+            Ok(PathInfo::Folder { contents, dev, .. }) => match contents.get_mut(name) {
+                Some(info) => {
+                    let old_count = info.count();
+                    info.hard_links(*dev, &mut unlinked);
+                    let (freed, removed) = remove(info);
+                    if removed {
+                        contents.remove(name);
+                        (freed, old_count)
+                    } else {
+                        // Only the links that were deleted are gone
+                        let mut kept = HashMap::new();
+                        info.hard_links(*dev, &mut kept);
+                        for (key, kept) in kept {
+                            if let Some(lost) = unlinked.get_mut(&key) {
+                                lost.found -= kept.found;
+                                lost.counted &= !kept.counted;
+                            }
+                        }
+                        unlinked.retain(|_, lost| lost.found > 0);
+                        (freed, old_count - info.count())
+                    }
+                }
+                None => return vec![],
+            },
+            _ => return vec![],
+        };
+        self.shrink(dir, freed, removed_count);
+        if unlinked.is_empty() {
+            return vec![];
+        }
+        self.relink(&mut unlinked);
+        unlinked
+            .into_iter()
+            .filter(|(_, lost)| !lost.shared)
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Collects the paths of every folder in this tree, relative to this entry at `path`, parents
+    /// first.
+    fn folders(&self, path: &mut Vec<OsString>, folders: &mut Vec<Vec<OsString>>) {
+        if let PathInfo::Folder { contents, .. } = self {
+            folders.push(path.clone());
+            for (name, info) in contents {
+                path.push(name.clone());
+                info.folders(path, folders);
+                path.pop();
+            }
+        }
+    }
+
+    /// Collects every error recorded in this tree along with the path, relative to this entry,
+    /// that produced it.
+    fn errors(&self, path: &mut Vec<OsString>, errors: &mut Vec<(io::ErrorKind, Vec<OsString>)>) {
+        match self {
+            PathInfo::Folder {
+                contents, error, ..
+            } => {
+                if let Some(kind) = error {
+                    errors.push((*kind, path.clone()));
+                }
+                for (name, info) in contents {
+                    path.push(name.clone());
+                    info.errors(path, errors);
+                    path.pop();
+                }
+            }
+            PathInfo::File { .. } | PathInfo::Skipped { .. } => {}
+            PathInfo::Error(kind) => errors.push((*kind, path.clone())),
+        }
+    }
 }
 
-impl PathInfo {
-    fn size(&self) -> u64 {
-        match *self {
-            PathInfo::Folder(s, _, _) => s,
-            PathInfo::File(s) => s,
+/// An operation performed on entries from the listing.
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    Delete,
+    Trash,
+    /// Runs a shell command with the entries' paths as arguments.
+    Command(String),
+}
+
+impl Action {
+    fn noun(&self) -> &'static str {
+        match self {
+            Action::Delete => "deletion",
+            Action::Trash => "trashing",
+            Action::Command(..) => "command",
+        }
+    }
+
+    /// Asks whether to perform this action on `target`, which describes the entries affected.
+    fn question(&self, target: &str) -> String {
+        match self {
+            Action::Delete => format!("Delete {}?", target),
+            Action::Trash => format!("Move {} to the trash?", target),
+            Action::Command(command) => format!("Run `{}` on {}?", command, target),
+        }
+    }
+
+    /// Performs this action on `paths`, given relative to `starting_dir`, and updates `root` to
+    /// match, forgetting the files with no links left from `inodes`. Returns a dialog describing
+    /// the outcome if there's anything to report.
+    fn perform(
+        &self,
+        paths: &[Vec<OsString>],
+        root: &mut PathInfo,
+        starting_dir: &Path,
+        inodes: &Mutex<HashSet<(u64, u64)>>,
+    ) -> Option<Dialog> {
+        let mut errors = vec![];
+        let output = match self {
+            Action::Command(command) => Some(run_command(
+                command,
+                paths
+                    .iter()
+                    .map(|path| join_path_to_vec(starting_dir, path.clone())),
+            )),
+            _ => None,
+        };
+        for path in paths {
+            let (name, dir) = match path.split_last() {
+                Some(split) => split,
+                None => continue,
+            };
+            let full_path = join_path_to_vec(starting_dir, path.clone());
+            let unlinked = root.remove_entry(dir, name, |info| match self {
+                Action::Delete => info.delete(&full_path, &mut errors),
+                Action::Trash => match trash::trash(&full_path) {
+                    Ok(()) => (info.contribution(), true),
+                    Err(e) => {
+                        errors.push((e.kind(), full_path.clone()));
+                        (Size::default(), false)
+                    }
+                },
+                // The command could have done anything, but at least forget what it removed
+                Action::Command(..) => match fs::symlink_metadata(&full_path) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => (info.contribution(), true),
+                    _ => (Size::default(), false),
+                },
+            });
+            let mut inodes = inodes.lock().unwrap();
+            for link in &unlinked {
+                inodes.remove(link);
+            }
+        }
+        match output {
+            Some(output) => Some(Dialog::Output(output)),
+            None if errors.is_empty() => None,
+            None => Some(Dialog::Failed(self.clone(), errors)),
+        }
+    }
+}
+
+/// Runs `command` through the shell with `paths` appended as separate arguments, returning its
+/// exit status and output.
+fn run_command(command: &str, paths: impl Iterator<Item = PathBuf>) -> String {
+    let output = process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", command))
+        .arg("rsdu")
+        .args(paths)
+        .stdin(process::Stdio::null())
+        .output();
+    match output {
+        Ok(output) => {
+            format!("{}\n", output.status)
+                + &String::from_utf8_lossy(&output.stdout)
+                + &String::from_utf8_lossy(&output.stderr)
+        }
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Something the main loop responds to by updating the interface.
+enum AppEvent {
+    Key(Key),
+    /// Sent regularly while a scan is running, so that its progress is shown.
+    Tick,
+    /// The result of a [`Refresh`], ready to replace the folder at the given path.
+    Refreshed(Vec<OsString>, PathInfo),
+    /// Entries that changed in watched folders, each given by its parent's path and its name,
+    /// along with what's there now.
+    Changed(Vec<(Vec<OsString>, OsString, Option<PathInfo>)>),
+}
+
+/// A rescan of one folder running in the background. The folder's old contents stay visible
+/// until it finishes.
+struct Refresh {
+    /// The folder being rescanned, relative to the starting directory.
+    path: Vec<OsString>,
+    progress: Arc<ScanProgress>,
+    start: Instant,
+    /// Set to stop the rescan, keeping the folder's old contents.
+    cancelled: Arc<AtomicBool>,
+}
+
+/// A popup shown over the listing, which captures the next key press.
+enum Dialog {
+    /// Asks for confirmation before performing an action on `paths`, which are relative to the
+    /// starting directory.
+    Confirm {
+        action: Action,
+        paths: Vec<Vec<OsString>>,
+        question: String,
+        summary: String,
+    },
+    /// Lists the paths an action failed for.
+    Failed(Action, Vec<(io::ErrorKind, PathBuf)>),
+    /// Prompts for a shell command to run on the marked entries.
+    Command(String),
+    /// Shows the output of a command.
+    Output(String),
+    /// Explains that an imported scan can't be changed on disk.
+    ReadOnly,
+    /// Explains that nothing can be changed on disk until the scan finishes.
+    Scanning,
+    /// Offers to quit after the scan was cancelled, or to browse what it found.
+    Cancelled,
+    /// Prompts for a pattern to find anywhere in the tree, explaining why the last one entered
+    /// was invalid, if it was.
+    Find {
+        pattern: String,
+        error: Option<String>,
+    },
+}
+
+impl Dialog {
+    /// The explanation shown for an action that touches the disk when it can't be performed.
+    fn unavailable(read_only: bool) -> Dialog {
+        if read_only {
+            Dialog::ReadOnly
+        } else {
+            Dialog::Scanning
+        }
+    }
+
+    fn confirm(action: Action, paths: Vec<Vec<OsString>>, target: &str, root: &PathInfo) -> Dialog {
+        let infos: Vec<&PathInfo> = paths.iter().filter_map(|path| root.get(path)).collect();
+        let mut links = HashMap::new();
+        for path in &paths {
+            if let (Some((_, dir)), Some(info)) = (path.split_last(), root.get(path)) {
+                if let Some(PathInfo::Folder { dev, .. }) = root.get(dir) {
+                    info.hard_links(*dev, &mut links);
+                }
+            }
+        }
+        // A hard-linked file only frees its space once all of its links are gone
+        let kept = links
+            .values()
+            .filter(|found| found.counted && found.found < found.links)
+            .fold(Size::default(), |acc, found| acc + found.size);
+        let size = infos
+            .iter()
+            .fold(Size::default(), |acc, info| acc + info.contribution())
+            - kept;
+        let count = count_contents(infos.iter().copied()).total();
+        Dialog::Confirm {
+            question: action.question(target),
+            summary: format!(
+                "{} ({} apparent) in {} items",
+                prettify_bytes(&size.disk),
+                prettify_bytes(&size.apparent),
+                count
+            ),
+            action,
+            paths,
+        }
+    }
+
+    fn title(&self) -> String {
+        match self {
+            Dialog::Confirm { action, .. } => format!(" Confirm {} ", action.noun()),
+            Dialog::Failed(action, _) => format!(" Failed {} ", action.noun()),
+            Dialog::Command(..) => " Run command on marked entries ".to_string(),
+            Dialog::Output(..) => " Command output ".to_string(),
+            Dialog::ReadOnly => " Imported scan ".to_string(),
+            Dialog::Scanning => " Scan in progress ".to_string(),
+            Dialog::Cancelled => " Scan cancelled ".to_string(),
+            Dialog::Find { .. } => " Find everywhere ".to_string(),
+        }
+    }
+
+    fn lines(&self) -> Vec<Spans<'_>> {
+        match self {
+            Dialog::Confirm {
+                question, summary, ..
+            } => vec![
+                Spans::from(question.as_str()),
+                Spans::from(summary.as_str()),
+                Spans::from(""),
+                Spans::from("[y]es  [n]o"),
+            ],
+            Dialog::Failed(_, errors) => errors
+                .iter()
+                .map(|(kind, path)| {
+                    Spans::from(vec![
+                        Span::styled(format!("{:?} ", kind), Style::default().fg(Color::Red)),
+                        Span::raw(String::from(path.to_string_lossy())),
+                    ])
+                })
+                .chain(vec![Spans::from(""), Spans::from("Press any key")])
+                .collect(),
+            Dialog::Command(command) => vec![
+                Spans::from("$ ".to_string() + command),
+                Spans::from(""),
+                Spans::from("The marked paths are appended as arguments"),
+            ],
+            Dialog::Output(output) => output
+                .lines()
+                .map(Spans::from)
+                .chain(vec![Spans::from(""), Spans::from("Press any key")])
+                .collect(),
+            Dialog::ReadOnly => vec![
+                Spans::from("This scan was imported from a file, so nothing on disk"),
+                Spans::from("can be deleted, trashed, refreshed or run on from here"),
+                Spans::from(""),
+                Spans::from("Press any key"),
+            ],
+            Dialog::Scanning => vec![
+                Spans::from("Nothing on disk can be deleted, trashed, refreshed or run"),
+                Spans::from("on until the scan finishes"),
+                Spans::from(""),
+                Spans::from("Press any key"),
+            ],
+            Dialog::Cancelled => vec![
+                Spans::from("Folders that weren't completely scanned are marked with !"),
+                Spans::from(""),
+                Spans::from("[q]uit  or press any other key to browse what was found"),
+            ],
+            Dialog::Find { pattern, error } => {
+                let mut lines = vec![
+                    Spans::from("Find: ".to_string() + pattern),
+                    Spans::from(""),
+                    Spans::from("Globs work like --exclude, or start with re: for a regex"),
+                ];
+                if let Some(error) = error {
+                    lines.push(Spans::from(Span::styled(
+                        error.as_str(),
+                        Style::default().fg(Color::Red),
+                    )));
+                }
+                lines
+            }
+        }
+    }
+}
+
+/// The screen shown below the header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum View {
+    /// The contents of the current directory.
+    Browse,
+    /// Every marked entry in the tree.
+    Marked,
+    /// The entries matching the last find.
+    Found,
+    /// The space used by each kind of file and each extension.
+    Stats,
+}
+
+/// The marked paths that aren't inside another marked folder, since acting on a folder already
+/// covers everything in it.
+fn outermost(marked: &BTreeSet<Vec<OsString>>) -> Vec<Vec<OsString>> {
+    let mut outermost: Vec<Vec<OsString>> = vec![];
+    // Sorting puts every path right after its ancestors
+    for path in marked {
+        if !outermost.last().is_some_and(|last| path.starts_with(last)) {
+            outermost.push(path.clone());
+        }
+    }
+    outermost
+}
+
+/// The entries listed for the folder at `dir`, in order.
+fn listing<'a>(
+    root: &'a PathInfo,
+    dir: &[OsString],
+    mode: SizeMode,
+    sort: Sort,
+    search: &Search,
+) -> Vec<(&'a OsString, &'a PathInfo)> {
+    search.apply(root.get(dir).unwrap().sorted(mode, sort).unwrap())
+}
+
+/// Moves the cursor in `state` to wherever the entry it was on in the `old` listing of a folder
+/// ended up in the `new` one, or to the top if it isn't listed anymore.
+fn follow_selection<'a>(
+    state: &mut ListState,
+    old: impl IntoIterator<Item = &'a OsString>,
+    new: &[(&OsString, &PathInfo)],
+) {
+    let position = old
+        .into_iter()
+        .nth(state.selected().unwrap_or(0))
+        .and_then(|name| new.iter().position(|(other, _)| *other == name));
+    state.select(Some(position.unwrap_or(0)));
+}
+
+/// Changes the search of the folder at `dir` with `change`, then moves the cursor in `state` to
+/// the first match at or after the entry it was on.
+fn update_search(
+    root: &PathInfo,
+    dir: &[OsString],
+    mode: SizeMode,
+    sort: Sort,
+    search: &mut Search,
+    state: &mut ListState,
+    change: impl FnOnce(&mut Search),
+) {
+    let old = listing(root, dir, mode, sort, search);
+    change(search);
+    let new = listing(root, dir, mode, sort, search);
+    follow_selection(state, old.iter().map(|(name, _)| *name), &new);
+    if let Some(found) = search.find(&new, state.selected().unwrap(), true, true) {
+        state.select(Some(found));
+    }
+}
+
+/// Brings the interface's state back in line with `root` after entries were removed from it,
+/// unmarking them and leaving the current directory if it's gone.
+fn forget_missing(
+    root: &mut PathInfo,
+    marked: &mut BTreeSet<Vec<OsString>>,
+    current_dir: &mut Vec<OsString>,
+    state: &mut ListState,
+    marked_state: &mut ListState,
+) {
+    marked.retain(|path| root.get(path).is_some());
+    while root.join(current_dir).is_err() {
+        current_dir.pop();
+    }
+    let dir_len = root.join(current_dir).unwrap().contents().unwrap().len();
+    let selected = state.selected().unwrap();
+    state.select(Some(selected.min(dir_len.saturating_sub(1))));
+    let selected = marked_state.selected().unwrap();
+    marked_state.select(Some(selected.min(marked.len().saturating_sub(1))));
+}
+
+fn join_path_to_vec(path: &Path, vec: Vec<OsString>) -> PathBuf {
+    let mut tmp_path = path.to_path_buf();
+    for comp in vec {
+        tmp_path = tmp_path.join(comp);
+    }
+    tmp_path
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = Args::parse();
+    if let Err(e) = args.scan.compile_excludes() {
+        Args::command()
+            .error(clap::error::ErrorKind::ValueValidation, e)
+            .exit();
+    }
+    let mut imported = args.import.as_ref().map(|file| {
+        import(file).unwrap_or_else(|e| {
+            eprintln!("rsdu: {}: {}", file.display(), e);
+            process::exit(2);
+        })
+    });
+    let read_only = imported.is_some();
+    let starting_dir = match (args.dir, &mut imported) {
+        (_, Some((dir, _))) => dir.clone(),
+        (Some(dir), None) => dir,
+        (None, None) => env::current_dir()?,
+    };
+    let mut imported = imported.map(|(_, root)| root);
+    let options = Arc::new(args.scan);
+    let size_mode = if args.apparent_size {
+        SizeMode::Apparent
+    } else {
+        SizeMode::Disk
+    };
+    let sort = Sort::new(args.sort, args.reverse, args.dirs_first);
+
+    if let Some(file) = &args.report.output {
+        let mut out: Box<dyn io::Write> = if file == Path::new("-") {
+            Box::new(io::BufWriter::new(io::stdout().lock()))
+        } else {
+            Box::new(io::BufWriter::new(fs::File::create(file)?))
+        };
+        match imported.take() {
+            Some(contents) => ncdu::write_export(&mut out, &contents, &starting_dir)?,
+            // Written while scanning, so the tree is never held in memory
+            None => ncdu::export_scan(&mut out, &starting_dir.canonicalize()?, &options)?,
+        }
+        return Ok(());
+    }
+
+    if args.report.report || !termion::is_tty(&io::stdout()) {
+        let dir = &starting_dir;
+        let contents = imported.take().unwrap_or_else(|| {
+            get_wrapped_contents(dir, &options, &Arc::default(), &Arc::default())
+        });
+        let report_options = report::ReportOptions {
+            mode: size_mode,
+            sort,
+            show_count: args.show_count,
+            depth: args.report.depth,
+            top: args.report.top,
+        };
+        match report::write_report(&mut io::stdout().lock(), &contents, dir, &report_options) {
+            // Stop quietly when piped into something like `head`
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+        let mut errors = vec![];
+        contents.errors(&mut vec![], &mut errors);
+        for (kind, path) in &errors {
+            eprintln!(
+                "rsdu: {}: {:?}",
+                join_path_to_vec(dir, path.clone()).display(),
+                kind
+            );
+        }
+        process::exit(if errors.is_empty() { 0 } else { 1 });
+    }
+
+    // Keys come from the terminal itself when stdin is the imported scan
+    let keys: Box<dyn Read + Send> = if termion::is_tty(&io::stdin()) {
+        Box::new(io::stdin())
+    } else {
+        match termion::get_tty() {
+            Ok(tty) => Box::new(tty),
+            Err(e) => {
+                eprintln!("rsdu: can't read keys from the terminal: {}", e);
+                process::exit(2);
+            }
+        }
+    };
+    let stdout = io::stdout().into_raw_mode().unwrap();
+    let stdout = MouseTerminal::from(stdout);
+    let stdout = AlternateScreen::from(stdout);
+    let backend = TermionBackend::new(stdout);
+    let mut terminal = Terminal::new(backend).unwrap();
+
+    let contents = Arc::new(Mutex::new(match imported {
+        Some(root) => root,
+        None => PathInfo::new_scanning_folder(Size::default(), 0, 0),
+    }));
+    let progress = Arc::new(ScanProgress::default());
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let scan_start = Instant::now();
+    let refresh: Arc<Mutex<Option<Refresh>>> = Arc::new(Mutex::new(None));
+    let (watcher, watch_error) = if args.watch {
+        match watch::Watcher::new(starting_dir.clone()) {
+            Ok(watcher) => (Some(Arc::new(watcher)), None),
+            Err(e) => (None, Some(e.to_string())),
+        }
+    } else {
+        (None, None)
+    };
+    let (tx, rx) = mpsc::channel();
+    if !read_only {
+        let contents_clone = Arc::clone(&contents);
+        let dir = starting_dir.clone();
+        let options_clone = Arc::clone(&options);
+        let progress_clone = Arc::clone(&progress);
+        let cancelled_clone = Arc::clone(&cancelled);
+        let watcher_clone = watcher.clone();
+        let watch_tx = tx.clone();
+        thread::spawn(move || {
+            scan(
+                &dir,
+                &options_clone,
+                &progress_clone,
+                &cancelled_clone,
+                &contents_clone,
+                None,
+            );
+            if let Some(watcher) = watcher_clone {
+                watch_changes(&watcher, &contents_clone, &dir, &options_clone, &watch_tx);
+            }
+        });
+    }
+
+    let key_tx = tx.clone();
+    thread::spawn(move || {
+        for event in keys.events() {
+            if let Ok(termion::event::Event::Key(key)) = event {
+                if key_tx.send(AppEvent::Key(key)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    // Redraw regularly while scanning, so that new entries show up as they're found
+    let contents_tick = Arc::clone(&contents);
+    let refresh_tick = Arc::clone(&refresh);
+    let tick_tx = tx.clone();
+    thread::spawn(move || {
+        // Once more after the scan finishes, to show its final state
+        let mut was_scanning = true;
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            let scanning = contents_tick.lock().unwrap().is_scanning()
+                || refresh_tick.lock().unwrap().is_some();
+            if (scanning || was_scanning) && tick_tx.send(AppEvent::Tick).is_err() {
+                break;
+            }
+            was_scanning = scanning;
+        }
+    });
+
+    let mut state = ListState::default();
+    state.select(Some(0));
+    let mut marked_state = ListState::default();
+    marked_state.select(Some(0));
+    let mut app = App {
+        contents,
+        refresh,
+        progress,
+        cancelled,
+        starting_dir,
+        read_only,
+        options,
+        watcher,
+        watch_error,
+        scan_start,
+        tx,
+        largest: args.largest,
+        min_size: args.min_size,
+        current_dir: vec![],
+        state,
+        listed: vec![],
+        size_mode,
+        sort,
+        search: Search::default(),
+        view: View::Browse,
+        dialog: None,
+        show_errors: false,
+        errors: vec![],
+        errors_seen: 0,
+        was_scanning: true,
+        show_count: args.show_count,
+        count_bars: false,
+        marked: BTreeSet::new(),
+        marked_state,
+        found: Found {
+            query: Query::Pattern(String::new()),
+            paths: vec![],
+        },
+        found_state: ListState::default(),
+        stats: StatsView::default(),
+    };
+    terminal.draw(|f| app.draw(f))?;
+    for event in rx {
+        if !app.handle(event) {
+            break;
+        }
+        terminal.draw(|f| app.draw(f))?;
+    }
+    Ok(())
+}
+
+/// The state of the interface, which belongs to the main loop. Only what the scanning threads
+/// use too is shared with them.
+struct App {
+    /// The scanned tree, which the initial scan and the watcher add to.
+    contents: Arc<Mutex<PathInfo>>,
+    /// The rescan of a folder running in the background, if any.
+    refresh: Arc<Mutex<Option<Refresh>>>,
+    /// The progress of the initial scan.
+    progress: Arc<ScanProgress>,
+    /// Set when the initial scan is cancelled.
+    cancelled: Arc<AtomicBool>,
+    starting_dir: PathBuf,
+    /// Whether the tree was imported, so that nothing on disk can be changed from here.
+    read_only: bool,
+    options: Arc<ScanOptions>,
+    watcher: Option<Arc<watch::Watcher>>,
+    /// Why watching was asked for but couldn't start.
+    watch_error: Option<String>,
+    scan_start: Instant,
+    /// Sends the results of refreshes to the main loop.
+    tx: mpsc::Sender<AppEvent>,
+    /// How many files the largest files view lists, and how large they must be to start with.
+    largest: usize,
+    min_size: u64,
+    current_dir: Vec<OsString>,
+    /// The cursor in the current folder's listing.
+    state: ListState,
+    /// The names in the current folder's listing as last drawn, for keeping the cursor on the
+    /// same entry when scanning reorders it.
+    listed: Vec<OsString>,
+    size_mode: SizeMode,
+    sort: Sort,
+    search: Search,
+    view: View,
+    dialog: Option<Dialog>,
+    show_errors: bool,
+    /// Collected again only when the tree changes, rather than on every redraw.
+    errors: Vec<(io::ErrorKind, Vec<OsString>)>,
+    /// The scan's error count when the errors were last collected.
+    errors_seen: u64,
+    /// Whether the initial scan was running at the last tick, to notice when it finishes.
+    was_scanning: bool,
+    show_count: bool,
+    count_bars: bool,
+    marked: BTreeSet<Vec<OsString>>,
+    marked_state: ListState,
+    found: Found,
+    found_state: ListState,
+    stats: StatsView,
+}
+
+impl App {
+    /// Whether a scan or refresh is running. Nothing on disk can be changed until it finishes,
+    /// since its results would overwrite the changes.
+    fn busy(&self) -> bool {
+        self.contents.lock().unwrap().is_scanning() || self.refresh.lock().unwrap().is_some()
+    }
+
+    fn listed_len(&self) -> usize {
+        listing(
+            &self.contents.lock().unwrap(),
+            &self.current_dir,
+            self.size_mode,
+            self.sort,
+            &self.search,
+        )
+        .len()
+    }
+
+    fn change_search(&mut self, change: impl FnOnce(&mut Search)) {
+        update_search(
+            &self.contents.lock().unwrap(),
+            &self.current_dir,
+            self.size_mode,
+            self.sort,
+            &mut self.search,
+            &mut self.state,
+            change,
+        )
+    }
+
+    fn collect_errors(&mut self) {
+        if self.show_errors {
+            let mut errors = vec![];
+            self.contents
+                .lock()
+                .unwrap()
+                .errors(&mut vec![], &mut errors);
+            self.errors = errors;
+        }
+    }
+
+    fn collect_stats(&mut self) {
+        if self.view == View::Stats {
+            self.stats
+                .collect(&self.contents.lock().unwrap(), &self.current_dir);
+        }
+    }
+
+    /// Updates the interface in response to `event`, returning whether to keep running.
+    fn handle(&mut self, event: AppEvent) -> bool {
+        // Whether entries may have been added or removed since the errors and stats were
+        // collected. While scanning, that's only looked at once it's done.
+        let mut new_errors = false;
+        let changed = match event {
+            AppEvent::Key(key) => return self.key(key),
+            AppEvent::Tick => {
+                let contents = Arc::clone(&self.contents);
+                let root = contents.lock().unwrap();
+                // What was scanned since the last tick can reorder the listing under the cursor
+                if self.view == View::Browse {
+                    let new = listing(
+                        &root,
+                        &self.current_dir,
+                        self.size_mode,
+                        self.sort,
+                        &self.search,
+                    );
+                    follow_selection(&mut self.state, &self.listed, &new);
+                }
+                let scanning = root.is_scanning();
+                let errors_found = self.progress.errors.load(Ordering::Relaxed);
+                // Errors can be counted before they're in the tree, which the end of the scan
+                // catches up with
+                new_errors = errors_found != self.errors_seen;
+                self.errors_seen = errors_found;
+                let finished = self.was_scanning && !scanning;
+                self.was_scanning = scanning;
+                finished
+            }
+            AppEvent::Refreshed(path, folder) => {
+                let mut contents = self.contents.lock().unwrap();
+                let refresh = self.refresh.lock().unwrap().take();
+                if !refresh.is_some_and(|refresh| refresh.cancelled.load(Ordering::Relaxed)) {
+                    contents.replace(&path, folder);
+                }
+                forget_missing(
+                    &mut contents,
+                    &mut self.marked,
+                    &mut self.current_dir,
+                    &mut self.state,
+                    &mut self.marked_state,
+                );
+                true
+            }
+            AppEvent::Changed(updates) => {
+                let mut contents = self.contents.lock().unwrap();
+                let mut unlinked = vec![];
+                for (dir, name, info) in updates {
+                    // The folder may have been deleted from the interface in the meantime
+                    if contents.join(&dir).is_err() {
+                        continue;
+                    }
+                    let mut path = dir.clone();
+                    path.push(name.clone());
+                    let old_file = match contents.get(&path) {
+                        Some(old @ PathInfo::File { ino, duplicate, .. }) => {
+                            Some((*ino, *duplicate, old.contribution(), old.count()))
+                        }
+                        _ => None,
+                    };
+                    match (info, old_file) {
+                        // A file that changed in place keeps whichever of its links was counted,
+                        // so it's replaced without removing a link
+                        (
+                            Some(PathInfo::File {
+                                size,
+                                links,
+                                ino,
+                                mtime,
+                                ..
+                            }),
+                            Some((old_ino, duplicate, freed, removed)),
+                        ) if ino == old_ino => {
+                            contents.shrink(&dir, freed, removed);
+                            let info = PathInfo::File {
+                                size,
+                                links,
+                                ino,
+                                mtime,
+                                duplicate,
+                            };
+                            contents.insert(&dir, vec![(name, info)]);
+                        }
+                        (info, _) => {
+                            unlinked.extend(
+                                contents
+                                    .remove_entry(&dir, &name, |old| (old.contribution(), true)),
+                            );
+                            if let Some(info) = info {
+                                contents.insert(&dir, vec![(name, info)]);
+                            }
+                        }
+                    }
+                }
+                let mut inodes = self.options.inodes.lock().unwrap();
+                for link in &unlinked {
+                    inodes.remove(link);
+                }
+                forget_missing(
+                    &mut contents,
+                    &mut self.marked,
+                    &mut self.current_dir,
+                    &mut self.state,
+                    &mut self.marked_state,
+                );
+                true
+            }
+        };
+        if changed || new_errors {
+            self.collect_errors();
+        }
+        if changed {
+            self.collect_stats();
+        }
+        true
+    }
+
+    /// Responds to a key press, returning whether to keep running.
+    fn key(&mut self, key: Key) -> bool {
+        if let Some(dialog) = self.dialog.take() {
+            return self.dialog_key(dialog, key);
+        }
+        if self.search.editing {
+            self.change_search(|search| match key {
+                Key::Char('\n') => search.editing = false,
+                Key::Esc => search.clear(),
+                Key::Backspace => {
+                    search.query.pop();
+                }
+                Key::Char(c) => search.query.push(c),
+                _ => {}
+            });
+            return true;
+        }
+        match key {
+            Key::Char('q') | Key::Ctrl('c') if self.contents.lock().unwrap().is_scanning() => {
+                self.cancelled.store(true, Ordering::Relaxed);
+                self.dialog = Some(Dialog::Cancelled);
+            }
+            Key::Char('q') | Key::Ctrl('c') => match &*self.refresh.lock().unwrap() {
+                // The tree is already complete, so stopping a refresh just keeps what's there
+                Some(refresh) => refresh.cancelled.store(true, Ordering::Relaxed),
+                None => return false,
+            },
+            Key::Char('M') => {
+                self.view = match self.view {
+                    View::Marked => View::Browse,
+                    _ => View::Marked,
+                };
+            }
+            Key::Char('D') | Key::Char('T') | Key::Char('!') if self.read_only || self.busy() => {
+                self.dialog = Some(Dialog::unavailable(self.read_only))
+            }
+            Key::Char('D') | Key::Char('T') | Key::Char('!') => {
+                let paths = outermost(&self.marked);
+                if !paths.is_empty() {
+                    let target = format!("{} marked entries", paths.len());
+                    let root = self.contents.lock().unwrap();
+                    self.dialog = Some(match key {
+                        Key::Char('D') => Dialog::confirm(Action::Delete, paths, &target, &root),
+                        Key::Char('T') => Dialog::confirm(Action::Trash, paths, &target, &root),
+                        _ => Dialog::Command(String::new()),
+                    });
+                }
+            }
+            Key::Char('S') => {
+                self.dialog = Some(Dialog::Find {
+                    pattern: String::new(),
+                    error: None,
+                })
+            }
+            Key::Char('L') => {
+                self.found = Found::largest(
+                    &self.contents.lock().unwrap(),
+                    self.size_mode,
+                    self.largest,
+                    self.min_size,
+                );
+                self.found_state.select(Some(0));
+                self.view = View::Found;
+            }
+            _ => match self.view {
+                View::Found => self.found_key(key),
+                // Also from the stats view itself, to count again
+                _ if key == Key::Char('U') => {
+                    self.stats.state.select(Some(0));
+                    self.view = View::Stats;
+                    self.collect_stats();
+                }
+                View::Stats => self.stats_key(key),
+                View::Marked => self.marked_key(key),
+                View::Browse => self.browse_key(key),
+            },
+        }
+        true
+    }
+
+    /// Responds to a key pressed while `dialog` was shown, returning whether to keep running.
+    fn dialog_key(&mut self, dialog: Dialog, key: Key) -> bool {
+        match (dialog, key) {
+            (Dialog::Confirm { action, paths, .. }, Key::Char('y')) => {
+                let mut contents = self.contents.lock().unwrap();
+                self.dialog = action.perform(
+                    &paths,
+                    &mut contents,
+                    &self.starting_dir,
+                    &self.options.inodes,
+                );
+                forget_missing(
+                    &mut contents,
+                    &mut self.marked,
+                    &mut self.current_dir,
+                    &mut self.state,
+                    &mut self.marked_state,
+                );
+                drop(contents);
+                self.collect_errors();
+                self.collect_stats();
+            }
+            (Dialog::Command(command), Key::Char('\n')) if !command.is_empty() => {
+                let paths = outermost(&self.marked);
+                let target = format!("{} marked entries", paths.len());
+                self.dialog = Some(Dialog::confirm(
+                    Action::Command(command),
+                    paths,
+                    &target,
+                    &self.contents.lock().unwrap(),
+                ));
+            }
+            (Dialog::Command(mut command), Key::Char(c)) if c != '\n' => {
+                command.push(c);
+                self.dialog = Some(Dialog::Command(command));
+            }
+            (Dialog::Cancelled, Key::Char('q')) | (Dialog::Cancelled, Key::Ctrl('c')) => {
+                return false
+            }
+            (Dialog::Find { pattern, .. }, Key::Char('\n')) if !pattern.is_empty() => {
+                match find::Pattern::new(&pattern) {
+                    Ok(compiled) => {
+                        let mut paths = vec![];
+                        find::find(
+                            &self.contents.lock().unwrap(),
+                            &mut self.starting_dir.clone(),
+                            &mut vec![],
+                            &compiled,
+                            &mut paths,
+                        );
+                        self.found = Found {
+                            query: Query::Pattern(pattern),
+                            paths,
+                        };
+                        self.found_state.select(Some(0));
+                        self.view = View::Found;
+                    }
+                    Err(e) => {
+                        self.dialog = Some(Dialog::Find {
+                            pattern,
+                            error: Some(e),
+                        })
+                    }
+                }
+            }
+            (Dialog::Find { mut pattern, .. }, Key::Char(c)) if c != '\n' => {
+                pattern.push(c);
+                self.dialog = Some(Dialog::Find {
+                    pattern,
+                    error: None,
+                });
+            }
+            (Dialog::Find { mut pattern, .. }, Key::Backspace) => {
+                pattern.pop();
+                self.dialog = Some(Dialog::Find {
+                    pattern,
+                    error: None,
+                });
+            }
+            (Dialog::Command(mut command), Key::Backspace) => {
+                command.pop();
+                self.dialog = Some(Dialog::Command(command));
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn found_key(&mut self, key: Key) {
+        let busy = self.busy();
+        let selected = self.found_state.selected().unwrap();
+        let mut root = self.contents.lock().unwrap();
+        match key {
+            Key::Char('j') | Key::Down => {
+                let len = self.found.entries(&root, self.size_mode).len();
+                self.found_state
+                    .select(Some((selected + 1).min(len.saturating_sub(1))))
+            }
+            Key::Char('k') | Key::Up => self.found_state.select(Some(selected.saturating_sub(1))),
+            Key::Char('\n') | Key::Char('l') | Key::Right => {
+                let target = self
+                    .found
+                    .entries(&root, self.size_mode)
+                    .get(selected)
+                    .map(|(path, _)| (*path).clone());
+                if let Some(mut dir) = target {
+                    let name = dir.pop().unwrap();
+                    if let Ok(PathInfo::Folder { selected, .. }) = root.join(&self.current_dir) {
+                        *selected = self.state.selected().unwrap();
+                    }
+                    self.search.clear();
+                    let position = listing(&root, &dir, self.size_mode, self.sort, &self.search)
+                        .iter()
+                        .position(|(other, _)| **other == name);
+                    self.state.select(Some(position.unwrap_or(0)));
+                    self.current_dir = dir;
+                    self.view = View::Browse;
+                }
+            }
+            Key::Char('d') | Key::Char('t') if self.read_only || busy => {
+                self.dialog = Some(Dialog::unavailable(self.read_only))
+            }
+            Key::Char('d') | Key::Char('t') | Key::Char(' ') => {
+                let target = self
+                    .found
+                    .entries(&root, self.size_mode)
+                    .get(selected)
+                    .map(|(path, _)| (*path).clone());
+                if let Some(path) = target {
+                    if key == Key::Char(' ') {
+                        if !self.marked.remove(&path) {
+                            self.marked.insert(path);
+                        }
+                        let len = self.found.entries(&root, self.size_mode).len();
+                        self.found_state.select(Some((selected + 1).min(len - 1)));
+                    } else {
+                        let action = if key == Key::Char('d') {
+                            Action::Delete
+                        } else {
+                            Action::Trash
+                        };
+                        let full_path = join_path_to_vec(&self.starting_dir, path.clone());
+                        self.dialog = Some(Dialog::confirm(
+                            action,
+                            vec![path],
+                            &full_path.to_string_lossy(),
+                            &root,
+                        ));
+                    }
+                }
+            }
+            Key::Char('+') | Key::Char('-') => {
+                if let Query::Largest { count, min_size } = self.found.query {
+                    let min_size = if key == Key::Char('+') {
+                        min_size.saturating_mul(2).max(1024)
+                    } else if min_size >= 2048 {
+                        min_size / 2
+                    } else {
+                        0
+                    };
+                    self.found = Found::largest(&root, self.size_mode, count, min_size);
+                    self.found_state.select(Some(0));
+                }
+            }
+            Key::Esc | Key::Char('h') | Key::Left => self.view = View::Browse,
+            _ => {}
+        }
+    }
+
+    fn stats_key(&mut self, key: Key) {
+        let selected = self.stats.state.selected().unwrap();
+        match key {
+            // Clamped to the number of extensions when drawn
+            Key::Char('j') | Key::Down => self.stats.state.select(Some(selected + 1)),
+            Key::Char('k') | Key::Up => self.stats.state.select(Some(selected.saturating_sub(1))),
+            Key::Char('w') => {
+                self.stats.this_folder = !self.stats.this_folder;
+                self.stats.state.select(Some(0));
+                self.collect_stats();
+            }
+            Key::Esc | Key::Char('h') | Key::Left => self.view = View::Browse,
+            _ => {}
+        }
+    }
+
+    fn marked_key(&mut self, key: Key) {
+        let selected = self.marked_state.selected().unwrap();
+        match key {
+            Key::Char('j') | Key::Down => self.marked_state.select(Some(
+                (selected + 1).min(self.marked.len().saturating_sub(1)),
+            )),
+            Key::Char('k') | Key::Up => self.marked_state.select(Some(selected.saturating_sub(1))),
+            Key::Char(' ') => {
+                if let Some(path) = self.marked.iter().nth(selected).cloned() {
+                    self.marked.remove(&path);
+                }
+                self.marked_state
+                    .select(Some(selected.min(self.marked.len().saturating_sub(1))));
+            }
+            Key::Esc | Key::Char('h') | Key::Left => self.view = View::Browse,
+            _ => {}
+        }
+    }
+
+    fn browse_key(&mut self, key: Key) {
+        let selected = self.state.selected().unwrap();
+        match key {
+            Key::Char('j') | Key::Down => {
+                let len = self.listed_len();
+                if len != 0 {
+                    self.state.select(Some((selected + 1).min(len - 1)));
+                }
+            }
+            Key::Char('k') | Key::Up => {
+                let len = self.listed_len();
+                if len != 0 {
+                    self.state
+                        .select(Some(selected.saturating_sub(1).min(len - 1)));
+                }
+            }
+            Key::Char('l') | Key::Right => {
+                let entering = matches!(
+                    listing(
+                        &self.contents.lock().unwrap(),
+                        &self.current_dir,
+                        self.size_mode,
+                        self.sort,
+                        &self.search,
+                    )
+                    .get(selected),
+                    Some((_, PathInfo::Folder { .. }))
+                );
+                // Anything else is left alone, since the cursor's position in a filtered
+                // listing can't be looked up in the full one used below
+                if entering {
+                    // The search belongs to the folder being left, so the cursor is moved to the
+                    // same entry in the full listing before it's remembered
+                    self.change_search(Search::clear);
+                    let selected = self.state.selected().unwrap();
+                    let mut root = self.contents.lock().unwrap();
+                    let joined = root.join(&self.current_dir).unwrap();
+                    if let PathInfo::Folder {
+                        selected: remembered,
+                        ..
+                    } = joined
+                    {
+                        *remembered = selected;
+                    }
+                    let target = match joined
+                        .sorted(self.size_mode, self.sort)
+                        .unwrap()
+                        .get(selected)
+                    {
+                        Some((name, PathInfo::Folder { .. })) => Some((*name).clone()),
+                        _ => None,
+                    };
+                    if let Some(name) = target {
+                        self.current_dir.push(name);
+                        if let Ok(PathInfo::Folder { selected, .. }) = root.join(&self.current_dir)
+                        {
+                            self.state.select(Some(*selected));
+                        }
+                    }
+                }
+            }
+            Key::Char('h') | Key::Left => {
+                self.change_search(Search::clear);
+                let mut root = self.contents.lock().unwrap();
+                if let Ok(PathInfo::Folder { selected, .. }) = root.join(&self.current_dir) {
+                    *selected = self.state.selected().unwrap();
+                }
+                self.current_dir.pop();
+                if let Ok(PathInfo::Folder { selected, .. }) = root.join(&self.current_dir) {
+                    self.state.select(Some(*selected));
+                }
+            }
+            Key::Char('r') | Key::Char('d') | Key::Char('t') if self.read_only || self.busy() => {
+                self.dialog = Some(Dialog::unavailable(self.read_only))
+            }
+            Key::Char('r') => self.refresh(),
+            Key::Char('d') | Key::Char('t') => {
+                let action = if key == Key::Char('d') {
+                    Action::Delete
+                } else {
+                    Action::Trash
+                };
+                let root = self.contents.lock().unwrap();
+                let listed = listing(
+                    &root,
+                    &self.current_dir,
+                    self.size_mode,
+                    self.sort,
+                    &self.search,
+                );
+                if let Some((name, _)) = listed.get(selected) {
+                    let mut path = self.current_dir.clone();
+                    path.push((*name).clone());
+                    let target = join_path_to_vec(&self.starting_dir, path.clone());
+                    self.dialog = Some(Dialog::confirm(
+                        action,
+                        vec![path],
+                        &target.to_string_lossy(),
+                        &root,
+                    ));
+                }
+            }
+            Key::Char(' ') => {
+                let root = self.contents.lock().unwrap();
+                let listed = listing(
+                    &root,
+                    &self.current_dir,
+                    self.size_mode,
+                    self.sort,
+                    &self.search,
+                );
+                if let Some((name, _)) = listed.get(selected) {
+                    let mut path = self.current_dir.clone();
+                    path.push((*name).clone());
+                    if !self.marked.remove(&path) {
+                        self.marked.insert(path);
+                    }
+                    self.state
+                        .select(Some((selected + 1).min(listed.len() - 1)));
+                }
+            }
+            Key::Char('/') => self.change_search(|search| {
+                search.query.clear();
+                search.editing = true;
+            }),
+            Key::Esc => self.change_search(Search::clear),
+            Key::Char('F') => self.change_search(|search| search.filter = !search.filter),
+            Key::Char('v') => self.search.relative = !self.search.relative,
+            // Only while searching, since `n` otherwise sorts by name
+            Key::Char('n') | Key::Char('N') if !self.search.query.is_empty() => {
+                let root = self.contents.lock().unwrap();
+                let listed = listing(
+                    &root,
+                    &self.current_dir,
+                    self.size_mode,
+                    self.sort,
+                    &self.search,
+                );
+                if let Some(found) =
+                    self.search
+                        .find(&listed, selected, key == Key::Char('n'), false)
+                {
+                    self.state.select(Some(found));
+                }
+            }
+            Key::Char('a')
+            | Key::Char('s')
+            | Key::Char('n')
+            | Key::Char('c')
+            | Key::Char('m')
+            | Key::Char('x')
+            | Key::Char('f') => {
+                let root = self.contents.lock().unwrap();
+                let old = listing(
+                    &root,
+                    &self.current_dir,
+                    self.size_mode,
+                    self.sort,
+                    &self.search,
+                );
+                match key {
+                    Key::Char('a') => self.size_mode = self.size_mode.toggled(),
+                    Key::Char('s') => self.sort = self.sort.by(SortKey::Size),
+                    Key::Char('n') => self.sort = self.sort.by(SortKey::Name),
+                    Key::Char('c') => self.sort = self.sort.by(SortKey::Count),
+                    Key::Char('m') => self.sort = self.sort.by(SortKey::Mtime),
+                    Key::Char('x') => self.sort = self.sort.by(SortKey::Extension),
+                    _ => self.sort.dirs_first = !self.sort.dirs_first,
+                }
+                let new = listing(
+                    &root,
+                    &self.current_dir,
+                    self.size_mode,
+                    self.sort,
+                    &self.search,
+                );
+                follow_selection(&mut self.state, old.iter().map(|(name, _)| *name), &new);
+            }
+            Key::Char('e') => {
+                self.show_errors = !self.show_errors;
+                self.collect_errors();
+            }
+            Key::Char('i') => self.show_count = !self.show_count,
+            Key::Char('b') => self.count_bars = !self.count_bars,
+            Key::Char('g') => self.state.select(Some(0)),
+            Key::Char('G') => {
+                let len = self.listed_len();
+                self.state.select(Some(len.saturating_sub(1)));
+            }
+            Key::Ctrl('d') | Key::Ctrl('f') => {
+                let len = self.listed_len();
+                if len != 0 {
+                    let page = termion::terminal_size().unwrap().1 as usize / 4;
+                    self.state.select(Some((selected + page).min(len - 1)));
+                }
+            }
+            Key::Ctrl('u') | Key::Ctrl('b') => {
+                let len = self.listed_len();
+                if len != 0 {
+                    let page = termion::terminal_size().unwrap().1 as usize / 4;
+                    self.state
+                        .select(Some(selected.saturating_sub(page).min(len - 1)));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Rescans the current folder in the background, keeping its old contents until it's done.
+    fn refresh(&mut self) {
+        let path = self.current_dir.clone();
+        let dir = join_path_to_vec(&self.starting_dir, path.clone());
+        // Links counted in the old contents are counted again wherever the rescan finds them
+        // first
+        let mut links = vec![];
+        if let Some(old) = self.contents.lock().unwrap().get(&path) {
+            old.counted_links(&mut links);
+        }
+        {
+            let mut inodes = self.options.inodes.lock().unwrap();
+            for link in &links {
+                inodes.remove(link);
+            }
+        }
+        let progress = Arc::new(ScanProgress::default());
+        let cancelled = Arc::new(AtomicBool::new(false));
+        *self.refresh.lock().unwrap() = Some(Refresh {
+            path: path.clone(),
+            progress: Arc::clone(&progress),
+            start: Instant::now(),
+            cancelled: Arc::clone(&cancelled),
+        });
+        let options = Arc::clone(&self.options);
+        let tx = self.tx.clone();
+        let watcher = self.watcher.clone();
+        thread::spawn(move || {
+            let folder = get_wrapped_contents(&dir, &options, &progress, &cancelled);
+            if cancelled.load(Ordering::Relaxed) {
+                // The old contents are kept, along with the links they counted
+                options.inodes.lock().unwrap().extend(links);
+            }
+            if let Some(watcher) = watcher.filter(|_| !cancelled.load(Ordering::Relaxed)) {
+                let mut folders = vec![];
+                folder.folders(&mut path.clone(), &mut folders);
+                watcher.watch(folders);
+            }
+            let _ = tx.send(AppEvent::Refreshed(path, folder));
+        });
+    }
+
+    fn draw<B: Backend>(&mut self, f: &mut Frame<B>) {
+        // Cloned so that the tree can be looked at while the rest of the state changes
+        let contents = Arc::clone(&self.contents);
+        let root = contents.lock().unwrap();
+        let refresh = Arc::clone(&self.refresh);
+        let refresh = refresh.lock().unwrap();
+        let scanning = root.is_scanning();
+        let mut constraints = vec![Constraint::Length(3), Constraint::Min(0)];
+        if self.show_errors {
+            constraints.push(Constraint::Percentage(30));
+        }
+        if scanning || refresh.is_some() {
+            constraints.push(Constraint::Length(5));
+        }
+        let searching = self.search.editing || !self.search.query.is_empty();
+        if searching {
+            constraints.push(Constraint::Length(1));
+        }
+        if !self.marked.is_empty() {
+            constraints.push(Constraint::Length(1));
+        }
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(constraints)
+            .split(f.size());
+        self.draw_header(f, chunks[0]);
+        if self.show_errors {
+            self.draw_errors(f, chunks[2]);
+        }
+        if scanning || refresh.is_some() {
+            self.draw_progress(f, chunks[2 + self.show_errors as usize], scanning, &refresh);
+        }
+        if searching {
+            let index = chunks.len() - 1 - !self.marked.is_empty() as usize;
+            self.draw_search(f, chunks[index], &root);
+        }
+        if !self.marked.is_empty() {
+            self.draw_marked_status(f, chunks[chunks.len() - 1], &root);
+        }
+
+        match self.view {
+            View::Browse => self.draw_browse(f, chunks[1], &root, &refresh),
+            View::Marked => self.draw_marked(f, chunks[1], &root),
+            View::Found => self.found.draw(
+                f,
+                chunks[1],
+                &root,
+                self.size_mode,
+                &self.marked,
+                &mut self.found_state,
+            ),
+            View::Stats => {
+                let folder = join_path_to_vec(&self.starting_dir, self.current_dir.clone());
+                self.stats
+                    .draw(f, chunks[1], self.size_mode, &folder, scanning)
+            }
+        }
+
+        if let Some(dialog) = &self.dialog {
+            let lines = dialog.lines();
+            let area = centered_rect(60, lines.len() as u16 + 2, f.size());
+            f.render_widget(Clear, area);
+            f.render_widget(
+                Paragraph::new(lines)
+                    .block(Block::default().title(dialog.title()).borders(Borders::ALL))
+                    .wrap(Wrap { trim: false }),
+                area,
+            );
+        }
+    }
+
+    /// Draws the current folder's path, titled with the state of the scan or watch.
+    fn draw_header<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let display_dir = join_path_to_vec(&self.starting_dir, self.current_dir.clone());
+        // Imported paths are shown as they were scanned, since they needn't refer to anything here
+        let display_dir = if self.read_only {
+            display_dir
+        } else {
+            display_dir.canonicalize().unwrap_or(display_dir)
+        };
+        let title = if self.read_only {
+            Span::raw(" rsdu (imported) ")
+        } else if self.cancelled.load(Ordering::Relaxed) {
+            Span::styled(
+                " rsdu (incomplete: the scan was cancelled) ",
+                Style::default().fg(Color::Red),
+            )
+        } else if let Some(error) = &self.watch_error {
+            Span::styled(
+                format!(" rsdu (not watching: {}) ", error),
+                Style::default().fg(Color::Yellow),
+            )
+        } else if let Some(watcher) = &self.watcher {
+            match watcher.unwatched().len() {
+                0 => Span::raw(" rsdu (watching) "),
+                unwatched => Span::styled(
+                    format!(" rsdu (watching, {} unwatched: see errors) ", unwatched),
+                    Style::default().fg(Color::Yellow),
+                ),
+            }
+        } else {
+            Span::raw(" rsdu ")
+        };
+        let block = Paragraph::new(String::from(display_dir.to_string_lossy()))
+            .block(Block::default().title(title).borders(Borders::ALL));
+        f.render_widget(block, area);
+    }
+
+    fn draw_errors<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let mut error_items: Vec<ListItem> = self
+            .errors
+            .iter()
+            .map(|(kind, path)| {
+                ListItem::new(Spans::from(vec![
+                    Span::styled(format!("{:?} ", kind), Style::default().fg(Color::Red)),
+                    Span::raw(String::from(
+                        join_path_to_vec(&self.starting_dir, path.clone()).to_string_lossy(),
+                    )),
+                ]))
+            })
+            .collect();
+        if let Some(watcher) = &self.watcher {
+            for (path, reason) in watcher.unwatched() {
+                error_items.push(ListItem::new(Spans::from(vec![
+                    Span::styled("Unwatched ", Style::default().fg(Color::Yellow)),
+                    Span::raw(format!(
+                        "{}: {}",
+                        join_path_to_vec(&self.starting_dir, path).to_string_lossy(),
+                        reason
+                    )),
+                ])));
+            }
         }
+        let count = error_items.len();
+        let error_list = List::new(error_items).block(
+            Block::default()
+                .title(format!(" Errors ({}) ", count))
+                .borders(Borders::ALL),
+        );
+        f.render_widget(error_list, area);
     }
 
-    fn join(&mut self, vec: &Vec<OsString>) -> Result<&mut PathInfo, io::Error> {
-        let mut curr_res = self;
-        for comp in vec {
-            match curr_res {
-                PathInfo::Folder(_, ref mut c, ..) => {
-                    if c.contains_key(comp) {
-                        curr_res = c.get_mut(comp).unwrap();
+    /// Draws the progress of the initial scan while it runs, or of a refresh.
+    fn draw_progress<B: Backend>(
+        &self,
+        f: &mut Frame<B>,
+        area: Rect,
+        scanning: bool,
+        refresh: &Option<Refresh>,
+    ) {
+        let running = if scanning {
+            Some((&self.progress, self.scan_start, " Scanning ".to_string()))
+        } else {
+            refresh.as_ref().map(|refresh| {
+                let dir = join_path_to_vec(&self.starting_dir, refresh.path.clone());
+                (
+                    &refresh.progress,
+                    refresh.start,
+                    format!(" Refreshing {} (q: stop) ", dir.to_string_lossy()),
+                )
+            })
+        };
+        if let Some((progress, start, title)) = running {
+            let lines: Vec<Spans> = progress
+                .lines(start.elapsed(), self.size_mode)
+                .into_iter()
+                .map(Spans::from)
+                .collect();
+            let panel =
+                Paragraph::new(lines).block(Block::default().title(title).borders(Borders::ALL));
+            f.render_widget(panel, area);
+        }
+    }
+
+    /// Draws the search being typed, or the number of matches and the keys for using them.
+    fn draw_search<B: Backend>(&self, f: &mut Frame<B>, area: Rect, root: &PathInfo) {
+        let search = &self.search;
+        let line = if search.editing {
+            format!(" /{}▏", search.query)
+        } else {
+            let matches = root
+                .get(&self.current_dir)
+                .unwrap()
+                .contents()
+                .unwrap()
+                .keys()
+                .filter(|name| search.matches(name))
+                .count();
+            format!(
+                " /{}  {} matches    n/N: next/previous  F: {}  {}Esc: clear",
+                search.query,
+                matches,
+                if search.filter {
+                    "show all"
+                } else {
+                    "hide others"
+                },
+                if search.filter {
+                    if search.relative {
+                        "v: sizes of folder  "
                     } else {
-                        return Err(io::Error::new(io::ErrorKind::Other, ""));
+                        "v: sizes of matches  "
                     }
+                } else {
+                    ""
+                }
+            )
+        };
+        f.render_widget(Paragraph::new(line), area);
+    }
+
+    /// Draws the number and size of the marked entries, and the keys for acting on them.
+    fn draw_marked_status<B: Backend>(&self, f: &mut Frame<B>, area: Rect, root: &PathInfo) {
+        let marked_size = outermost(&self.marked)
+            .iter()
+            .filter_map(|path| root.get(path))
+            .fold(Size::default(), |acc, info| acc + info.contribution());
+        let status = Paragraph::new(format!(
+            " {} marked, {}    M: view  D: delete  T: trash  !: run command",
+            self.marked.len(),
+            prettify_bytes(&marked_size.get(self.size_mode))
+        ))
+        .style(Style::default().add_modifier(Modifier::REVERSED));
+        f.render_widget(status, area);
+    }
+
+    /// Draws the entries of the current folder.
+    fn draw_browse<B: Backend>(
+        &mut self,
+        f: &mut Frame<B>,
+        area: Rect,
+        root: &PathInfo,
+        refresh: &Option<Refresh>,
+    ) {
+        let size_mode = self.size_mode;
+        let joined_contents = root.get(&self.current_dir).unwrap();
+        let listed = listing(root, &self.current_dir, size_mode, self.sort, &self.search);
+        let relative = self.search.is_filtering() && self.search.relative;
+        let (total, total_count) = if relative {
+            (
+                listed
+                    .iter()
+                    .fold(Size::default(), |acc, (_, info)| acc + info.contribution())
+                    .get(size_mode),
+                count_contents(listed.iter().map(|(_, info)| *info)).total(),
+            )
+        } else {
+            // Not counting the folder itself
+            (
+                joined_contents.size(size_mode),
+                joined_contents.count().total() - 1,
+            )
+        };
+        let spinner = SPINNER[self.scan_start.elapsed().as_millis() as usize / 100 % SPINNER.len()];
+        let mut items: Vec<ListItem> = vec![];
+        let mut path_vec = self.current_dir.clone();
+        for &(path, info) in &listed {
+            path_vec.push(path.clone());
+            let mark = if self.marked.contains(&path_vec) {
+                '*'
+            } else {
+                ' '
+            };
+            let refreshing = refresh
+                .as_ref()
+                .is_some_and(|refresh| refresh.path == path_vec);
+            path_vec.pop();
+            let count = if self.show_count {
+                count_column(info)
+            } else {
+                String::new()
+            };
+            let size = match info {
+                PathInfo::Skipped { reason, .. } if reason.is_exclusion() => {
+                    " ".repeat(20 + count.len())
+                }
+                _ if self.count_bars => {
+                    pad_and_prettify_bytes(&info.size(size_mode))
+                        + &count
+                        + &size_bar(&info.count().total(), &total_count)
                 }
-                PathInfo::File(..) => return Err(io::Error::new(io::ErrorKind::Other, "")),
+                _ => {
+                    pad_and_prettify_bytes(&info.size(size_mode))
+                        + &count
+                        + &size_bar(&info.size(size_mode), &total)
+                }
+            };
+            let flag = if info.is_scanning() || refreshing {
+                spinner
+            } else {
+                info.flag()
             };
+            let item = ListItem::new(Spans::from(Span::raw(
+                mark.to_string()
+                    + &flag.to_string()
+                    + &size
+                    + &path.as_os_str().to_string_lossy()
+                    + &info.suffix(),
+            )));
+            items.push(match info {
+                PathInfo::Skipped { .. } => item.style(Style::default().fg(Color::DarkGray)),
+                _ if mark == '*' => item.style(Style::default().fg(Color::Yellow)),
+                _ => item,
+            });
         }
-        match curr_res {
-            PathInfo::Folder(..) => Ok(curr_res),
-            PathInfo::File(..) => Err(io::Error::new(io::ErrorKind::Other, "")),
+        let paths = List::new(items)
+            .block(
+                Block::default()
+                    .title(format!(
+                        " {}{}: {}{} (sorted {}) ",
+                        size_mode.label(),
+                        if relative { " of matches" } else { "" },
+                        prettify_bytes(&total),
+                        if self.show_count || self.count_bars {
+                            format!(" in {} items", total_count)
+                        } else {
+                            String::new()
+                        },
+                        self.sort.label()
+                    ))
+                    .borders(Borders::ALL),
+            )
+            .highlight_style(
+                Style::default()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::BOLD),
+            );
+        self.listed = listed.iter().map(|(name, _)| (*name).clone()).collect();
+        // Filtering can leave fewer entries than the cursor position
+        if let Some(selected) = self.state.selected() {
+            self.state
+                .select(Some(selected.min(listed.len().saturating_sub(1))));
         }
+        f.render_stateful_widget(paths, area, &mut self.state);
     }
 
-    fn contents(&self) -> Result<&BTreeMap<OsString, PathInfo>, io::Error> {
-        match self {
-            PathInfo::Folder(_, c, ..) => Ok(c),
-            PathInfo::File(..) => Err(io::Error::new(io::ErrorKind::Other, "")),
+    /// Draws every marked entry, wherever it is in the tree.
+    fn draw_marked<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect, root: &PathInfo) {
+        let items: Vec<ListItem> = self
+            .marked
+            .iter()
+            .map(|path| {
+                let size = root.get(path).map_or(0, |info| info.size(self.size_mode));
+                ListItem::new(Spans::from(Span::raw(
+                    pad_and_prettify_bytes(&size)
+                        + "  "
+                        + &join_path_to_vec(&self.starting_dir, path.clone()).to_string_lossy(),
+                )))
+            })
+            .collect();
+        let paths = List::new(items)
+            .block(
+                Block::default()
+                    .title(" Marked entries (space: unmark, M: back) ")
+                    .borders(Borders::ALL),
+            )
+            .highlight_style(
+                Style::default()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::BOLD),
+            );
+        f.render_stateful_widget(paths, area, &mut self.marked_state);
+    }
+}
+
+/// Another rust rewrite of ncdu.
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// The directory to scan [default: the current directory]
+    #[arg(value_parser = parse_dir)]
+    dir: Option<PathBuf>,
+    /// Browse a scan exported by ncdu or by --output instead of scanning, reading stdin if FILE
+    /// is -
+    #[arg(short = 'f', long, value_name = "FILE", conflicts_with = "dir")]
+    import: Option<PathBuf>,
+    /// Keep sizes up to date after scanning by watching the scanned folders for changes
+    #[arg(short = 'w', long, conflicts_with = "import")]
+    watch: bool,
+    /// Show apparent sizes instead of disk usage when starting
+    #[arg(short = 'A', long)]
+    apparent_size: bool,
+    /// What to sort each folder's entries by
+    #[arg(short = 's', long, value_name = "KEY", default_value = "size")]
+    sort: SortKey,
+    /// Sort in the opposite direction to usual for the sort key
+    #[arg(long)]
+    reverse: bool,
+    /// List folders before files
+    #[arg(long)]
+    dirs_first: bool,
+    /// Show the number of items in each folder next to its size
+    #[arg(short = 'i', long)]
+    show_count: bool,
+    /// How many files the largest files view (L) lists
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    largest: usize,
+    /// Leave files smaller than SIZE, like 512k, 10M or 1.5G, out of the largest files view
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "0")]
+    min_size: u64,
+    #[command(flatten)]
+    scan: ScanOptions,
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+/// Options for printing the scanned tree instead of opening the interface.
+#[derive(clap::Args)]
+struct ReportArgs {
+    /// Print the scanned tree to stdout instead of browsing it, which is the default when stdout
+    /// isn't a terminal
+    #[arg(short = 'r', long)]
+    report: bool,
+    /// Only print entries up to this many levels below the scanned directory
+    #[arg(short = 'd', long, value_name = "N")]
+    depth: Option<usize>,
+    /// Only print the first N entries of each directory, in sorted order
+    #[arg(short = 'n', long, value_name = "N")]
+    top: Option<usize>,
+    /// Export the scan to a file in ncdu's JSON format as it runs instead of browsing it, or to
+    /// stdout if FILE is -
+    #[arg(short = 'o', long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+/// Reads an exported scan from `file`, or from stdin if it's `-`.
+fn import(file: &Path) -> io::Result<(PathBuf, PathInfo)> {
+    if file == Path::new("-") {
+        ncdu::read_export(io::BufReader::new(io::stdin().lock()))
+    } else {
+        ncdu::read_export(io::BufReader::new(fs::File::open(file)?))
+    }
+}
+
+fn parse_dir(arg: &str) -> Result<PathBuf, String> {
+    let dir = PathBuf::from(arg);
+    match fs::metadata(&dir) {
+        Ok(metadata) if metadata.is_dir() => Ok(dir),
+        Ok(_) => Err("not a directory".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parses a number of bytes with an optional binary unit suffix, like `10M`.
+fn parse_size(arg: &str) -> Result<u64, String> {
+    let arg = arg.trim();
+    let digits = arg
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(digits);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| "expected a number, optionally followed by a unit".to_string())?;
+    let exponent = match unit
+        .trim_end_matches(['B', 'b'])
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "" => 0,
+        "k" | "ki" => 1,
+        "m" | "mi" => 2,
+        "g" | "gi" => 3,
+        "t" | "ti" => 4,
+        "p" | "pi" => 5,
+        _ => return Err(format!("unknown unit '{}'", unit)),
+    };
+    Ok((number * 1024_f64.powi(exponent)) as u64)
+}
+
+/// Settings controlling which parts of the filesystem a scan descends into.
+#[derive(clap::Args, Clone, Debug, Default)]
+struct ScanOptions {
+    /// Stay on the filesystem of the scanned directory
+    #[arg(short = 'x', long)]
+    one_file_system: bool,
+    /// Skip Linux pseudo filesystems such as procfs and sysfs
+    #[arg(long)]
+    exclude_kernfs: bool,
+    /// Count the size of every hard link to a file instead of only the first
+    #[arg(short = 'l', long)]
+    count_links: bool,
+    /// Exclude entries matching a glob pattern, matched against the full path if it contains a
+    /// `/` or against the entry's name otherwise
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// Exclude entries matching any of the patterns listed in a file, one per line
+    #[arg(short = 'X', long, value_name = "FILE")]
+    exclude_from: Vec<PathBuf>,
+    /// Exclude directories containing a valid CACHEDIR.TAG file
+    #[arg(long)]
+    exclude_caches: bool,
+    #[arg(skip)]
+    excludes: Excludes,
+    /// Use a shell command's output as the size of each file instead of its metadata, where
+    /// `{}` is replaced by the file's path, e.g. 'zstd -c {} | wc -c'
+    #[arg(long, value_name = "COMMAND", value_parser = SizeCommand::parse)]
+    size_command: Option<SizeCommand>,
+    /// The number of threads to scan with [default: the number of CPUs]
+    #[arg(
+        short = 't',
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    threads: Option<usize>,
+    /// The `(st_dev, st_ino)` pairs of every file with more than one hard link counted so far.
+    /// This is shared between scans so that a refresh doesn't count links again that are
+    /// already counted elsewhere in the tree.
+    #[arg(skip)]
+    inodes: Arc<Mutex<HashSet<(u64, u64)>>>,
+}
+
+/// A user-supplied command printing the size of a file.
+#[derive(Clone, Debug)]
+struct SizeCommand {
+    template: String,
+    /// Previous results by path, along with the file's modification time when they were
+    /// computed. This is shared between scans so refreshing only reruns the command for files
+    /// that changed.
+    cache: Arc<Mutex<HashMap<PathBuf, (SystemTime, u64)>>>,
+}
+
+impl SizeCommand {
+    fn parse(template: &str) -> Result<SizeCommand, String> {
+        if !template.contains("{}") {
+            return Err("the command must contain `{}`".to_string());
         }
+        Ok(SizeCommand {
+            template: template.to_string(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        })
     }
 
-    fn sorted(&self) -> Result<Vec<(&OsString, &PathInfo)>, io::Error> {
-        match self {
-            PathInfo::Folder(_, c, _) => {
-                let mut contents_vec = Vec::from_iter(c.iter());
-                contents_vec.sort_by(|(_, a), (_, b)| a.size().cmp(&b.size()).reverse());
-                Ok(contents_vec)
+    fn size(&self, path: &Path, metadata: &fs::Metadata) -> io::Result<u64> {
+        let mtime = metadata.modified()?;
+        if let Some((cached_mtime, size)) = self.cache.lock().unwrap().get(path) {
+            if *cached_mtime == mtime {
+                return Ok(*size);
             }
-            _ => Err(io::Error::new(io::ErrorKind::Other, "")),
         }
+        // The path is passed as a positional parameter so it never needs to be escaped
+        let output = process::Command::new("sh")
+            .arg("-c")
+            .arg(self.template.replace("{}", "\"$1\""))
+            .arg("rsdu")
+            .arg(path)
+            .stdin(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(output.status.to_string()));
+        }
+        let size = String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .next()
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "expected a size"))?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), (mtime, size));
+        Ok(size)
     }
 }
 
-fn join_path_to_vec(path: &Path, vec: Vec<OsString>) -> PathBuf {
-    let mut tmp_path = path.to_path_buf();
-    for comp in vec {
-        tmp_path = tmp_path.join(comp);
+impl ScanOptions {
+    /// Compiles the patterns given by `--exclude` and `--exclude-from` so they can be matched
+    /// while scanning.
+    fn compile_excludes(&mut self) -> Result<(), String> {
+        let mut patterns = self.exclude.clone();
+        for file in &self.exclude_from {
+            let list = fs::read_to_string(file)
+                .map_err(|e| format!("couldn't read '{}': {}", file.display(), e))?;
+            patterns.extend(
+                list.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+        self.excludes = Excludes::new(&patterns).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn file_size(&self, path: &Path, metadata: &fs::Metadata) -> io::Result<Size> {
+        match &self.size_command {
+            Some(command) if metadata.is_file() => {
+                let size = command.size(path, metadata)?;
+                Ok(Size {
+                    apparent: size,
+                    disk: size,
+                })
+            }
+            _ => Ok(Size::from(metadata)),
+        }
+    }
+
+    /// Determines whether the entry at `path` shouldn't be scanned, where `parent_dev` is the
+    /// device containing its parent directory and `root_dev` the one the scan started on.
+    fn skip_reason(
+        &self,
+        path: &Path,
+        metadata: &fs::Metadata,
+        parent_dev: u64,
+        root_dev: u64,
+    ) -> Option<SkipReason> {
+        if self.excludes.is_match(path) {
+            return Some(SkipReason::Pattern);
+        }
+        if !metadata.is_dir() {
+            return None;
+        }
+        if self.one_file_system && metadata.dev() != root_dev {
+            Some(SkipReason::OtherFilesystem)
+        } else if self.exclude_kernfs && metadata.dev() != parent_dev && is_kernfs(path) {
+            Some(SkipReason::KernelFilesystem)
+        } else if self.exclude_caches && has_cachedir_tag(path) {
+            Some(SkipReason::CacheDir)
+        } else {
+            None
+        }
+    }
+
+    /// Records the file described by `metadata` as counted, returning whether another hard link
+    /// to it already was.
+    fn already_counted(&self, metadata: &fs::Metadata) -> bool {
+        metadata.nlink() > 1
+            && !self.count_links
+            && !self
+                .inodes
+                .lock()
+                .unwrap()
+                .insert((metadata.dev(), metadata.ino()))
+    }
+
+    /// The entry for the file at `path`, which isn't marked as a duplicate hard link, since that
+    /// depends on the rest of the scan.
+    fn file_info(&self, path: &Path, metadata: &fs::Metadata) -> PathInfo {
+        match self.file_size(path, metadata) {
+            Ok(size) => PathInfo::File {
+                size,
+                links: metadata.nlink(),
+                ino: metadata.ino(),
+                mtime: metadata.mtime(),
+                duplicate: false,
+            },
+            Err(e) => PathInfo::Error(e.kind()),
+        }
     }
-    tmp_path
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let stdout = io::stdout().into_raw_mode().unwrap();
-    let stdout = MouseTerminal::from(stdout);
-    let stdout = AlternateScreen::from(stdout);
-    let backend = TermionBackend::new(stdout);
-    let mut terminal = Terminal::new(backend).unwrap();
+/// Compiled exclude patterns, split by whether they apply to entry names or whole paths.
+#[derive(Clone, Debug, Default)]
+struct Excludes {
+    names: GlobSet,
+    paths: GlobSet,
+}
 
-    let starting_dir = match get_starting_dir() {
-        Ok(dir) => Arc::new(Mutex::new(dir)),
-        Err(e) => panic!("{}", e),
-    };
+impl Excludes {
+    fn new(patterns: &[String]) -> Result<Excludes, globset::Error> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns {
+            let pattern = pattern.trim_end_matches('/');
+            if pattern.contains('/') {
+                // Relative patterns may match starting at any directory, like in `.gitignore`
+                let pattern = if pattern.starts_with('/') || pattern.starts_with("**") {
+                    pattern.to_string()
+                } else {
+                    format!("**/{}", pattern)
+                };
+                paths.add(GlobBuilder::new(&pattern).literal_separator(true).build()?);
+            } else {
+                names.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+            }
+        }
+        Ok(Excludes {
+            names: names.build()?,
+            paths: paths.build()?,
+        })
+    }
 
-    let state = Arc::new(Mutex::new(ListState::default()));
-    state.lock().unwrap().select(Some(0));
+    fn is_match(&self, path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|name| self.names.is_match(name))
+            || self.paths.is_match(path)
+    }
+}
 
-    let (tx, rx) = std::sync::mpsc::channel();
+/// Counters updated by a running scan, so that its progress can be shown.
+#[derive(Default)]
+struct ScanProgress {
+    entries: AtomicU64,
+    dirs: AtomicU64,
+    apparent: AtomicU64,
+    disk: AtomicU64,
+    errors: AtomicU64,
+    /// The directory most recently entered.
+    current: Mutex<PathBuf>,
+}
 
-    let contents = Arc::new(Mutex::new(PathInfo::Folder(0, BTreeMap::new(), 0)));
-    let contents_clone = Arc::clone(&contents);
-    let dir: Vec<OsString> = vec![];
-    let current_dir = Arc::new(Mutex::new(dir));
-    let starting_dir_clone = Arc::clone(&starting_dir);
-    thread::spawn(move || {
-        *contents_clone.lock().unwrap() = get_wrapped_contents(&starting_dir_clone.lock().unwrap());
-        tx.send(0).unwrap();
-    });
+impl ScanProgress {
+    fn add_size(&self, size: Size) {
+        self.apparent.fetch_add(size.apparent, Ordering::Relaxed);
+        self.disk.fetch_add(size.disk, Ordering::Relaxed);
+    }
 
-    let starting_dir_clone = Arc::clone(&starting_dir);
-    let starting_dir_copy = starting_dir_clone.lock().unwrap().clone();
-    let mut dot_pos = 0;
-    let mut dot_fwd = true;
-    loop {
-        terminal
-            .draw(|f| {
-                let chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints(
-                        [
-                            Constraint::Length(3),
-                            Constraint::Length((f.size().height - 6) / 2),
-                            Constraint::Length(3),
-                        ]
-                        .as_ref(),
-                    )
-                    .split(f.size());
-                let display_dir_string = String::from(starting_dir_copy.to_string_lossy());
-                let block = Paragraph::new(display_dir_string)
-                    .block(Block::default().title(" rsdu ").borders(Borders::ALL));
-                f.render_widget(block, chunks[0]);
-                let blank1 = Block::default();
-                f.render_widget(blank1, chunks[1]);
-                let msg = Paragraph::new(
-                    "Scanning".to_string()
-                        + &" ".repeat(dot_pos)
-                        + "..."
-                        + &" ".repeat(6 - dot_pos),
-                )
-                .alignment(Alignment::Center)
-                .block(Block::default());
-                f.render_widget(msg, chunks[2]);
-            })
-            .unwrap();
-        if dot_pos == 6 {
-            dot_fwd = false;
-        } else if dot_pos == 0 {
-            dot_fwd = true;
-        }
-        if dot_fwd {
-            dot_pos += 1;
-        } else {
-            dot_pos -= 1;
-        }
-        // TODO: Determine better way of terminating immediately without having to wait for last
-        // sleep
-        thread::sleep(std::time::Duration::from_millis(50));
-        match rx.try_recv() {
-            Ok(_) => break,
-            Err(_) => {}
-        }
-    }
-
-    let contents_clone = Arc::clone(&contents);
-    let current_dir_clone = Arc::clone(&current_dir);
-    let starting_dir_clone = Arc::clone(&starting_dir);
-    let state_clone = Arc::clone(&state);
-
-    let mut draw = move || {
-        terminal
-            .draw(|f| {
-                let chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
-                    .split(f.size());
-                let mut items: Vec<ListItem> = vec![];
-                let mut contents_access = contents_clone.lock().unwrap();
-                let joined_contents = contents_access
-                    .join(&*current_dir_clone.lock().unwrap())
-                    .unwrap();
-                let display_dir = join_path_to_vec(
-                    &*starting_dir_clone.lock().unwrap(),
-                    (current_dir_clone.lock().unwrap()).clone(),
-                )
-                .canonicalize()
-                .unwrap();
-                let display_dir_string = String::from(display_dir.to_string_lossy());
-                let block = Paragraph::new(display_dir_string)
-                    .block(Block::default().title(" rsdu ").borders(Borders::ALL));
-                f.render_widget(block, chunks[0]);
-
-                for (path, info) in joined_contents.sorted().unwrap() {
-                    items.push(ListItem::new(Spans::from(Span::raw(
-                        String::from(pad_and_prettify_bytes(&info.size()))
-                            + &size_bar(&info.size(), &joined_contents.size())
-                            + &path.as_os_str().to_string_lossy()
-                            + match info {
-                                PathInfo::Folder(..) => "/",
-                                PathInfo::File(..) => "",
-                            },
-                    ))));
-                }
-                let paths = List::new(items)
-                    .block(Block::default().borders(Borders::ALL))
-                    .highlight_style(
-                        Style::default()
-                            .fg(Color::Blue)
-                            .add_modifier(Modifier::BOLD),
-                    );
-                f.render_stateful_widget(paths, chunks[1], &mut state_clone.lock().unwrap());
-            })
-            .unwrap();
-    };
+    fn size(&self) -> Size {
+        Size {
+            apparent: self.apparent.load(Ordering::Relaxed),
+            disk: self.disk.load(Ordering::Relaxed),
+        }
+    }
 
-    draw();
+    /// Describes the progress so far, `elapsed` after the scan started.
+    fn lines(&self, elapsed: Duration, mode: SizeMode) -> Vec<String> {
+        let entries = self.entries.load(Ordering::Relaxed);
+        let size = self.size().get(mode);
+        let seconds = elapsed.as_secs_f64().max(0.001);
+        vec![
+            format!(
+                "{} entries in {} directories, {} total, {} errors",
+                entries,
+                self.dirs.load(Ordering::Relaxed),
+                prettify_bytes(&size),
+                self.errors.load(Ordering::Relaxed)
+            ),
+            format!(
+                "{} elapsed, {:.0} entries/s, {}/s",
+                format_duration(elapsed),
+                entries as f64 / seconds,
+                prettify_bytes(&((size as f64 / seconds) as u64))
+            ),
+            String::from(self.current.lock().unwrap().to_string_lossy()),
+        ]
+    }
+}
 
-    let contents_clone = Arc::clone(&contents);
-    let current_dir_clone = Arc::clone(&current_dir);
-    let starting_dir_clone = Arc::clone(&starting_dir);
-    let state_clone = Arc::clone(&state);
+/// The number of entries a scan thread reads before adding them to the tree, so that large
+/// folders fill in gradually without taking the tree's lock for every entry.
+const BATCH_SIZE: usize = 1024;
+/// How long a scan thread holds on to a partial batch, for folders that are slow to read.
+const BATCH_INTERVAL: Duration = Duration::from_millis(100);
 
-    let stdin = io::stdin();
-    for event in stdin.events() {
-        match event.unwrap() {
-            termion::event::Event::Key(key) => {
-                match key {
-                    // TODO: implement deletion with confirmation
-                    // TODO: implement trashing with the give `trash` command found on the shell's path
-                    // TODO: implement selection and application of deletion and trashing commands
-                    // to all selected files
-                    Key::Char('q') => break,
-                    Key::Char('j') | Key::Down => {
-                        let dir_len = (&contents_clone)
-                            .lock()
-                            .unwrap()
-                            .join(&*current_dir_clone.lock().unwrap())
-                            .unwrap()
-                            .contents()
-                            .unwrap()
-                            .len();
-                        if dir_len != 0 {
-                            let new_state =
-                                (((state_clone.lock().unwrap().selected().unwrap() as isize) + 1)
-                                    .max(0) as usize)
-                                    .min(dir_len - 1);
-                            state_clone.lock().unwrap().select(Some(new_state));
-                        }
-                    }
-                    Key::Char('k') | Key::Up => {
-                        let dir_len = (&contents_clone)
-                            .lock()
-                            .unwrap()
-                            .join(&*current_dir_clone.lock().unwrap())
-                            .unwrap()
-                            .contents()
-                            .unwrap()
-                            .len();
-                        if dir_len != 0 {
-                            let new_state =
-                                (((state_clone.lock().unwrap().selected().unwrap() as isize) - 1)
-                                    .max(0) as usize)
-                                    .min(dir_len - 1);
-                            state_clone.lock().unwrap().select(Some(new_state));
-                        }
-                    }
-                    Key::Char('l') | Key::Right => {
-                        let mut drawn_dir_access = current_dir_clone.lock().unwrap();
-                        let mut contents_access = (&contents_clone).lock().unwrap();
-                        let mut joined = contents_access.join(&*drawn_dir_access).unwrap();
-                        match joined {
-                            PathInfo::Folder(.., ref mut s) => {
-                                *s = state_clone.lock().unwrap().selected().unwrap();
-                            }
-                            PathInfo::File(..) => panic!(),
-                        }
-                        let sorted = joined.sorted().unwrap();
-                        let (target_os_string, info) = sorted
-                            .iter()
-                            .nth(state_clone.lock().unwrap().selected().unwrap())
-                            .unwrap();
-                        match info {
-                            PathInfo::Folder(..) => {
-                                (*drawn_dir_access).push(OsString::from(target_os_string));
-                                joined = contents_access.join(&*drawn_dir_access).unwrap();
-                                state_clone.lock().unwrap().select(match joined {
-                                    PathInfo::Folder(_, _, s) => {
-                                        let new_state = *s as usize;
-                                        Some(new_state)
-                                    }
-                                    PathInfo::File(..) => panic!(),
-                                });
-                            }
-                            PathInfo::File(..) => {}
-                        }
-                    }
-                    Key::Char('h') | Key::Left => {
-                        let mut drawn_dir_access = current_dir_clone.lock().unwrap();
-                        let mut contents_access = (&contents_clone).lock().unwrap();
-                        let mut joined = contents_access.join(&*drawn_dir_access).unwrap();
-                        match joined {
-                            PathInfo::Folder(.., ref mut s) => {
-                                *s = state_clone.lock().unwrap().selected().unwrap();
-                            }
-                            PathInfo::File(..) => panic!(),
-                        }
-                        (*drawn_dir_access).pop();
-                        joined = contents_access.join(&*drawn_dir_access).unwrap();
-                        state_clone.lock().unwrap().select(match joined {
-                            PathInfo::Folder(.., s) => Some(*s as usize),
-                            PathInfo::File(..) => panic!(),
-                        });
-                    }
-                    Key::Char('r') => {
-                        let drawn_dir_clone = current_dir_clone.lock().unwrap().clone();
-                        let mut contents_access = (&contents_clone).lock().unwrap();
-                        let joined = contents_access.join(&drawn_dir_clone).unwrap();
-                        *joined = get_wrapped_contents(&mut join_path_to_vec(
-                            &starting_dir_clone.lock().unwrap(),
-                            drawn_dir_clone,
-                        ));
+/// State shared between all of the threads taking part in a scan.
+struct ScanContext {
+    options: ScanOptions,
+    /// The device containing the directory the scan started from.
+    root_dev: u64,
+    progress: Arc<ScanProgress>,
+    /// Set when the scan should stop as soon as possible.
+    cancelled: Arc<AtomicBool>,
+    /// The tree being filled in.
+    tree: Arc<Mutex<PathInfo>>,
+    queue: Mutex<WorkQueue>,
+    /// Notified when jobs are added to `queue`, or when it runs out of work entirely.
+    queue_changed: Condvar,
+    /// Sent the path of each folder once its own entries are all in the tree, along with the
+    /// error encountered reading them, if any.
+    listed: Option<mpsc::Sender<(Vec<OsString>, Option<io::ErrorKind>)>>,
+}
+
+/// Directories waiting to be read by the scan's threads.
+#[derive(Default)]
+struct WorkQueue {
+    jobs: Vec<DirJob>,
+    /// The number of jobs currently being read, which may still add more jobs.
+    busy: usize,
+}
+
+/// A directory waiting to be read.
+struct DirJob {
+    dir: PathBuf,
+    /// The device containing `dir`.
+    dev: u64,
+    state: Arc<DirState>,
+}
+
+/// Tracks when a directory and everything below it has been scanned.
+struct DirState {
+    /// The location of the directory in the tree.
+    path: Vec<OsString>,
+    /// The number of subdirectories still being scanned, plus one until the directory's own
+    /// entries have all been read.
+    pending: AtomicUsize,
+    error: Mutex<Option<io::ErrorKind>>,
+    parent: Option<Arc<DirState>>,
+}
+
+impl DirState {
+    fn new(path: Vec<OsString>, parent: Option<Arc<DirState>>) -> Arc<DirState> {
+        Arc::new(DirState {
+            path,
+            pending: AtomicUsize::new(1),
+            error: Mutex::new(None),
+            parent,
+        })
+    }
+}
+
+impl ScanContext {
+    /// Reads queued directories until there are none left and no other thread can add more.
+    fn work(&self) {
+        loop {
+            let job = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    if let Some(job) = queue.jobs.pop() {
+                        queue.busy += 1;
+                        break job;
                     }
-                    Key::Char('g') => state_clone.lock().unwrap().select(Some(0)),
-                    Key::Char('G') => {
-                        let dir_len = (&contents_clone)
-                            .lock()
-                            .unwrap()
-                            .join(&*current_dir_clone.lock().unwrap())
-                            .unwrap()
-                            .contents()
-                            .unwrap()
-                            .len();
-                        state_clone.lock().unwrap().select(Some(dir_len - 1));
+                    if queue.busy == 0 {
+                        return;
                     }
-                    Key::Ctrl('d') | Key::Ctrl('f') => {
-                        let dir_len = (&contents_clone)
-                            .lock()
-                            .unwrap()
-                            .join(&*current_dir_clone.lock().unwrap())
-                            .unwrap()
-                            .contents()
-                            .unwrap()
-                            .len();
-                        if dir_len != 0 {
-                            let new_state = (((state_clone.lock().unwrap().selected().unwrap()
-                                as isize)
-                                + (termion::terminal_size().unwrap().1 as isize / 4))
-                                .max(0) as usize)
-                                .min(dir_len);
-                            state_clone.lock().unwrap().select(Some(new_state));
-                        }
+                    queue = self.queue_changed.wait(queue).unwrap();
+                }
+            };
+            self.read_dir(job);
+            let mut queue = self.queue.lock().unwrap();
+            queue.busy -= 1;
+            if queue.busy == 0 && queue.jobs.is_empty() {
+                self.queue_changed.notify_all();
+            }
+        }
+    }
+
+    /// Reads the entries of a queued directory, adding them to the tree in batches and queueing
+    /// its subdirectories. Errors never abort the scan; entries that can't be read are recorded
+    /// as [`PathInfo::Error`], and a failure to read the directory itself is recorded on its
+    /// folder.
+    fn read_dir(&self, job: DirJob) {
+        let DirJob { dir, dev, state } = job;
+        let progress = &self.progress;
+        if self.cancelled.load(Ordering::Relaxed) {
+            self.listed(state, Some(io::ErrorKind::Interrupted));
+            return;
+        }
+        progress.dirs.fetch_add(1, Ordering::Relaxed);
+        *progress.current.lock().unwrap() = dir.clone();
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                progress.errors.fetch_add(1, Ordering::Relaxed);
+                self.listed(state, Some(e.kind()));
+                return;
+            }
+        };
+
+        let mut error = None;
+        let mut batch = Vec::new();
+        let mut subdirs = Vec::new();
+        let mut last_flush = Instant::now();
+        for entry in entries {
+            if self.cancelled.load(Ordering::Relaxed) {
+                error = Some(io::ErrorKind::Interrupted);
+                break;
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    progress.errors.fetch_add(1, Ordering::Relaxed);
+                    error.get_or_insert(e.kind());
+                    continue;
+                }
+            };
+            progress.entries.fetch_add(1, Ordering::Relaxed);
+            let name = entry.file_name();
+            let path = entry.path();
+            let info = match fs::symlink_metadata(&path) {
+                Ok(metadata) => match self
+                    .options
+                    .skip_reason(&path, &metadata, dev, self.root_dev)
+                {
+                    Some(reason) => PathInfo::Skipped {
+                        reason,
+                        dir: metadata.is_dir(),
+                    },
+                    None if metadata.is_dir() => {
+                        progress.add_size(Size::from(&metadata));
+                        subdirs.push((name.clone(), path, metadata.dev()));
+                        PathInfo::new_scanning_folder(
+                            Size::from(&metadata),
+                            metadata.mtime(),
+                            metadata.dev(),
+                        )
                     }
-                    Key::Ctrl('u') | Key::Ctrl('b') => {
-                        let dir_len = (&contents_clone)
-                            .lock()
-                            .unwrap()
-                            .join(&*current_dir_clone.lock().unwrap())
-                            .unwrap()
-                            .contents()
-                            .unwrap()
-                            .len();
-                        if dir_len != 0 {
-                            let new_state = (((state_clone.lock().unwrap().selected().unwrap()
-                                as isize)
-                                - (termion::terminal_size().unwrap().1 as isize / 4))
-                                .max(0) as usize)
-                                .min(dir_len);
-                            state_clone.lock().unwrap().select(Some(new_state));
+                    None => {
+                        let mut info = self.options.file_info(&path, &metadata);
+                        if let PathInfo::File { duplicate, .. } = &mut info {
+                            *duplicate = self.options.already_counted(&metadata);
                         }
+                        info
                     }
-                    _ => (),
+                },
+                Err(e) => PathInfo::Error(e.kind()),
+            };
+            match &info {
+                PathInfo::File { .. } => progress.add_size(info.contribution()),
+                PathInfo::Error(..) => {
+                    progress.errors.fetch_add(1, Ordering::Relaxed);
                 }
+                _ => {}
             }
-            _ => (),
-        };
-        draw();
+            batch.push((name, info));
+            if batch.len() == BATCH_SIZE || last_flush.elapsed() >= BATCH_INTERVAL {
+                self.flush(&state, &mut batch, &mut subdirs);
+                last_flush = Instant::now();
+            }
+        }
+        self.flush(&state, &mut batch, &mut subdirs);
+        self.listed(state, error);
+    }
+
+    /// Records that the entries of the directory tracked by `state` have all been read, or that
+    /// reading them stopped because of `error`.
+    fn listed(&self, state: Arc<DirState>, error: Option<io::ErrorKind>) {
+        *state.error.lock().unwrap() = error;
+        if let Some(listed) = &self.listed {
+            let _ = listed.send((state.path.clone(), error));
+        }
+        self.done(state);
+    }
+
+    /// Adds a batch of entries read from the directory tracked by `state` to the tree, then
+    /// queues the subdirectories among them. Their folders have to be in the tree before they're
+    /// queued, so that their own entries have somewhere to go.
+    fn flush(
+        &self,
+        state: &Arc<DirState>,
+        batch: &mut Vec<(OsString, PathInfo)>,
+        subdirs: &mut Vec<(OsString, PathBuf, u64)>,
+    ) {
+        if batch.is_empty() {
+            return;
+        }
+        self.tree
+            .lock()
+            .unwrap()
+            .insert(&state.path, std::mem::take(batch));
+        if subdirs.is_empty() {
+            return;
+        }
+        state.pending.fetch_add(subdirs.len(), Ordering::AcqRel);
+        let mut queue = self.queue.lock().unwrap();
+        for (name, dir, dev) in subdirs.drain(..) {
+            let mut path = state.path.clone();
+            path.push(name);
+            queue.jobs.push(DirJob {
+                dir,
+                dev,
+                state: DirState::new(path, Some(Arc::clone(state))),
+            });
+        }
+        self.queue_changed.notify_all();
+    }
+
+    /// Records that one of the things `state` was waiting for has finished, marking its folder
+    /// as scanned if nothing else remains, and likewise for its ancestors.
+    fn done(&self, state: Arc<DirState>) {
+        let mut state = state;
+        while state.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let error = *state.error.lock().unwrap();
+            self.tree.lock().unwrap().finish(&state.path, error);
+            state = match &state.parent {
+                Some(parent) => Arc::clone(parent),
+                None => return,
+            };
+        }
     }
-    Ok(())
 }
 
-fn get_starting_dir() -> Result<PathBuf, io::Error> {
-    let current_dir = match env::current_dir() {
-        Ok(dir) => dir,
-        Err(e) => return Err(e),
-    };
-    let args = env::args().collect::<Vec<String>>();
-    match args.len() {
-        1 => Ok(current_dir),
-        2 => Ok(PathBuf::from(&args[1])),
-        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "")),
+/// Scans `dir` into a new tree, returning it once the scan is complete or `cancelled` is set.
+fn get_wrapped_contents(
+    dir: &Path,
+    options: &ScanOptions,
+    progress: &Arc<ScanProgress>,
+    cancelled: &Arc<AtomicBool>,
+) -> PathInfo {
+    let tree = Arc::new(Mutex::new(PathInfo::new_scanning_folder(
+        Size::default(),
+        0,
+        0,
+    )));
+    scan(dir, options, progress, cancelled, &tree, None);
+    Arc::try_unwrap(tree).unwrap().into_inner().unwrap()
+}
+
+/// Scans the single entry at `path` outside of a full scan, to keep a watched tree up to date.
+/// Hard links are checked against the ones counted by earlier scans, but `--one-file-system` and
+/// `--exclude-kernfs` aren't applied to the entry itself.
+fn scan_entry(path: &Path, metadata: &fs::Metadata, options: &ScanOptions) -> PathInfo {
+    let dir = metadata.is_dir();
+    if options.excludes.is_match(path) {
+        return PathInfo::Skipped {
+            reason: SkipReason::Pattern,
+            dir,
+        };
+    }
+    if dir && options.exclude_caches && has_cachedir_tag(path) {
+        return PathInfo::Skipped {
+            reason: SkipReason::CacheDir,
+            dir,
+        };
+    }
+    if dir {
+        let mut folder = get_wrapped_contents(path, options, &Arc::default(), &Arc::default());
+        if let PathInfo::Folder { size, .. } = &mut folder {
+            *size += Size::from(metadata);
+        }
+        return folder;
     }
+    let mut info = options.file_info(path, metadata);
+    if let PathInfo::File { duplicate, .. } = &mut info {
+        *duplicate = options.already_counted(metadata);
+    }
+    info
 }
 
-fn get_wrapped_contents(dir: &Path) -> PathInfo {
-    let threads = Arc::new(Mutex::new(1));
-    let max_threads = num_cpus::get();
-    let contents = get_contents(dir, threads, max_threads).unwrap();
-    PathInfo::Folder(sum_contents(&contents), contents, 0)
+/// Watches every folder in `tree`, which was scanned from `dir`, and sends the entries that change
+/// below them to the main loop as they do, until it stops listening.
+fn watch_changes(
+    watcher: &watch::Watcher,
+    tree: &Mutex<PathInfo>,
+    dir: &Path,
+    options: &ScanOptions,
+    tx: &mpsc::Sender<AppEvent>,
+) {
+    let mut folders = vec![];
+    tree.lock().unwrap().folders(&mut vec![], &mut folders);
+    watcher.watch(folders);
+    loop {
+        let events = match watcher.read_events() {
+            Ok(events) => events,
+            Err(e) => {
+                watcher.mark_unwatched(vec![], &e.to_string());
+                let _ = tx.send(AppEvent::Tick);
+                return;
+            }
+        };
+        // Several events for the same entry only need it to be looked at once
+        let mut changed: BTreeMap<(Vec<OsString>, OsString), bool> = BTreeMap::new();
+        for event in events {
+            match event {
+                watch::Event::Changed { dir, name, change } => {
+                    *changed.entry((dir, name)).or_default() |= change == watch::Change::Created;
+                }
+                watch::Event::Overflowed => watcher.mark_unwatched(
+                    vec![],
+                    "too many changes at once, so some were missed; press r to rescan",
+                ),
+            }
+        }
+        let mut updates = vec![];
+        for ((parent, name), created) in changed {
+            let mut path = parent.clone();
+            path.push(name.clone());
+            let full_path = join_path_to_vec(dir, path.clone());
+            let info = match fs::symlink_metadata(&full_path) {
+                // Writes inside a folder are reported by the folder's own watch
+                Ok(metadata) if metadata.is_dir() && !created => continue,
+                Ok(metadata) => Some(scan_entry(&full_path, &metadata, options)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => Some(PathInfo::Error(e.kind())),
+            };
+            match &info {
+                Some(folder @ PathInfo::Folder { .. }) => {
+                    let mut folders = vec![];
+                    folder.folders(&mut path, &mut folders);
+                    watcher.watch(folders);
+                }
+                None => watcher.unwatch(&path),
+                Some(_) => {}
+            }
+            updates.push((parent, name, info));
+        }
+        if tx.send(AppEvent::Changed(updates)).is_err() {
+            return;
+        }
+    }
 }
 
-fn get_contents(
+/// Scans `dir` into `tree`, which must be a folder created by [`PathInfo::new_scanning_folder`],
+/// using a fixed pool of threads that take directories from a shared queue. Entries are added to
+/// `tree` as they're read, so it can be browsed while the scan runs. Setting `cancelled` stops
+/// the scan early, leaving folders that weren't completely read marked as interrupted. The path
+/// of each folder is sent to `listed`, if given, once its own entries are all in `tree`.
+fn scan(
     dir: &Path,
-    threads: Arc<Mutex<usize>>,
-    max_threads: usize,
-) -> Result<BTreeMap<OsString, PathInfo>, io::Error> {
-    let contents = Arc::new(Mutex::new(BTreeMap::new()));
-    let entries = match fs::read_dir(dir) {
-        Ok(entries) => entries,
-        Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => return Ok(BTreeMap::new()),
-        Err(e) => panic!("{}", e),
+    options: &ScanOptions,
+    progress: &Arc<ScanProgress>,
+    cancelled: &Arc<AtomicBool>,
+    tree: &Arc<Mutex<PathInfo>>,
+    listed: Option<mpsc::Sender<(Vec<OsString>, Option<io::ErrorKind>)>>,
+) {
+    let (root_dev, root_mtime) = match fs::metadata(dir) {
+        Ok(metadata) => (metadata.dev(), metadata.mtime()),
+        Err(e) => {
+            progress.errors.fetch_add(1, Ordering::Relaxed);
+            tree.lock().unwrap().finish(&[], Some(e.kind()));
+            return;
+        }
     };
+    if let PathInfo::Folder { dev, mtime, .. } = &mut *tree.lock().unwrap() {
+        *dev = root_dev;
+        *mtime = (*mtime).max(root_mtime);
+    }
+    let context = Arc::new(ScanContext {
+        options: options.clone(),
+        root_dev,
+        progress: Arc::clone(progress),
+        cancelled: Arc::clone(cancelled),
+        tree: Arc::clone(tree),
+        queue: Mutex::new(WorkQueue::default()),
+        queue_changed: Condvar::new(),
+        listed,
+    });
+    context.queue.lock().unwrap().jobs.push(DirJob {
+        dir: dir.to_path_buf(),
+        dev: root_dev,
+        state: DirState::new(vec![], None),
+    });
+    let threads = options.threads.unwrap_or_else(num_cpus::get);
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            let context = Arc::clone(&context);
+            thread::spawn(move || context.work())
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
 
-    let mut handlers = Vec::new();
+/// Magic numbers from `linux/magic.h` of the pseudo filesystems skipped by `--exclude-kernfs`.
+#[cfg(target_os = "linux")]
+const KERNFS_MAGICS: [u64; 12] = [
+    0x4249_4e4d, // binfmt_misc
+    0xcafe_4a11, // bpf
+    0x0027_e0eb, // cgroup
+    0x6367_7270, // cgroup2
+    0x6462_6720, // debugfs
+    0x0000_1cd1, // devpts
+    0x0000_9fa0, // proc
+    0x6165_676c, // pstore
+    0x7363_6673, // securityfs
+    0xf97c_ff8c, // selinuxfs
+    0x6265_6572, // sysfs
+    0x7472_6163, // tracefs
+];
 
-    for entry in entries {
-        let contents_clone = Arc::clone(&contents);
-        // TODO: handle errors in threads
-        let threads_depth_clone = Arc::clone(&threads);
-        let task = move || {
-            let safe_entry = entry.unwrap();
-            let metadata = fs::symlink_metadata(safe_entry.path()).unwrap();
-            if metadata.is_dir() {
-                let sub_contents =
-                    get_contents(&safe_entry.path(), threads_depth_clone, max_threads).unwrap();
-                contents_clone.lock().unwrap().insert(
-                    OsString::from(safe_entry.path().components().last().unwrap().as_os_str()),
-                    PathInfo::Folder(
-                        sum_contents(&sub_contents) + metadata.len(),
-                        sub_contents,
-                        0,
-                    ),
-                );
-            } else {
-                contents_clone.lock().unwrap().insert(
-                    OsString::from(safe_entry.path().components().last().unwrap().as_os_str()),
-                    PathInfo::File(metadata.len()),
-                );
-            };
-        };
-        if *threads.lock().unwrap() < max_threads {
-            let threads_breadth_clone = Arc::clone(&threads);
-            handlers.push(thread::spawn(move || {
-                *threads_breadth_clone.lock().unwrap() += 1;
-                task();
-                *threads_breadth_clone.lock().unwrap() -= 1;
-            }));
-        } else {
-            task();
-        }
+#[cfg(target_os = "linux")]
+fn is_kernfs(path: &Path) -> bool {
+    use std::{ffi::CString, mem, os::unix::ffi::OsStrExt};
+
+    let c_path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => c_path,
+        Err(_) => return false,
+    };
+    let mut buf: libc::statfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut buf) } != 0 {
+        return false;
     }
+    KERNFS_MAGICS.contains(&(buf.f_type as u64))
+}
 
-    for handler in handlers {
-        match handler.join() {
-            Ok(_) => {}
-            Err(_) => {}
-        };
+#[cfg(not(target_os = "linux"))]
+fn is_kernfs(_path: &Path) -> bool {
+    false
+}
+
+/// Checks for a `CACHEDIR.TAG` file as described by https://bford.info/cachedir/.
+fn has_cachedir_tag(dir: &Path) -> bool {
+    const SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+    let mut buf = [0; SIGNATURE.len()];
+    match fs::File::open(dir.join("CACHEDIR.TAG")) {
+        Ok(mut file) => file.read_exact(&mut buf).is_ok() && buf == SIGNATURE,
+        Err(_) => false,
     }
+}
 
-    Ok(Arc::try_unwrap(contents).unwrap().into_inner().unwrap())
+fn sum_contents(contents: &BTreeMap<OsString, PathInfo>) -> Size {
+    contents
+        .values()
+        .fold(Size::default(), |acc, x| acc + x.contribution())
 }
 
-fn sum_contents(contents: &BTreeMap<OsString, PathInfo>) -> u64 {
-    contents.values().fold(0, |acc, x| acc + x.size())
+fn count_contents<'a>(infos: impl IntoIterator<Item = &'a PathInfo>) -> Count {
+    infos
+        .into_iter()
+        .fold(Count::default(), |acc, info| acc + info.count())
 }
 
 fn prettify_bytes(bytes: &u64) -> String {
@@ -485,20 +3351,226 @@ fn prettify_bytes(bytes: &u64) -> String {
     )
 }
 
+/// Formats `duration` like `1h 02m 03s`, leaving out hours and minutes when they're zero.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, s) => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
 fn pad_and_prettify_bytes(bytes: &u64) -> String {
     let pretty_bytes = prettify_bytes(bytes);
     " ".repeat(8 - pretty_bytes.len()) + &pretty_bytes
 }
 
+/// The number of items in `info` if it's a folder, padded to line up with other entries.
+fn count_column(info: &PathInfo) -> String {
+    match info {
+        PathInfo::Folder { count, .. } => format!(" {:>9}", count.total()),
+        _ => " ".repeat(10),
+    }
+}
+
+/// A rectangle horizontally covering `percent_x` of `r` and `height` rows tall, centered in `r`.
+fn centered_rect(percent_x: u16, height: u16, r: Rect) -> Rect {
+    let width = r.width * percent_x / 100;
+    let height = height.min(r.height);
+    Rect::new(
+        r.x + (r.width - width) / 2,
+        r.y + (r.height - height) / 2,
+        width,
+        height,
+    )
+}
+
 fn size_bar(child_bytes: &u64, parent_bytes: &u64) -> String {
     let bar_components = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
-    let fraction = *child_bytes as f64 / *parent_bytes as f64;
+    // Duplicate hard links aren't counted in their parent's size, so the fraction can exceed one
+    let fraction = if *parent_bytes == 0 {
+        0_f64
+    } else {
+        (*child_bytes as f64 / *parent_bytes as f64).min(1_f64)
+    };
     let floored_frac = (fraction * 8_f64).floor().max(0_f64);
+    if floored_frac as usize == 8 {
+        return " [".to_string() + &"█".repeat(8) + "] ";
+    }
     let mut bar = "█".repeat(floored_frac as usize)
-        + &bar_components[(((fraction - (floored_frac / 8_f64)) * 64_f64).round() as usize)
-            .min(8)
-            .max(0)]
-        .to_string();
-    bar += &" ".repeat((7 - floored_frac as usize).min(8));
+        + &bar_components[(((fraction - (floored_frac / 8_f64)) * 64_f64).round() as usize).min(8)]
+            .to_string();
+    bar += &" ".repeat(7_usize.saturating_sub(floored_frac as usize));
     " [".to_string() + &bar + "] "
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_with_binary_units() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512k"), Ok(512 * 1024));
+        assert_eq!(parse_size("10M"), Ok(10 << 20));
+        assert_eq!(parse_size("10MiB"), Ok(10 << 20));
+        assert_eq!(parse_size("10mb"), Ok(10 << 20));
+        assert_eq!(parse_size("1.5G"), Ok(3 << 29));
+        assert_eq!(parse_size(" 2T "), Ok(2 << 40));
+        assert_eq!(parse_size("100B"), Ok(100));
+        // Too large to fit, which saturates rather than wrapping
+        assert_eq!(parse_size("10000000P"), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        for arg in ["", "k", "1.2.3M", "10X", "10 kilo", "-5", "M10"] {
+            assert!(parse_size(arg).is_err(), "{}", arg);
+        }
+    }
+
+    fn file(size: u64, links: u64, ino: u64, duplicate: bool) -> PathInfo {
+        PathInfo::File {
+            size: Size {
+                apparent: size,
+                disk: size,
+            },
+            links,
+            ino,
+            mtime: 0,
+            duplicate,
+        }
+    }
+
+    fn folder(contents: Vec<(&str, PathInfo)>) -> PathInfo {
+        let contents = contents
+            .into_iter()
+            .map(|(name, info)| (OsString::from(name), info))
+            .collect();
+        PathInfo::new_folder(Size::default(), 0, 1, contents, None)
+    }
+
+    fn path(names: &[&str]) -> Vec<OsString> {
+        names.iter().map(OsString::from).collect()
+    }
+
+    /// Two links to the same file, of which the one in `old` was counted.
+    fn linked_tree() -> PathInfo {
+        folder(vec![
+            ("old", folder(vec![("data", file(1000, 2, 7, false))])),
+            ("new", folder(vec![("data", file(1000, 2, 7, true))])),
+        ])
+    }
+
+    fn size_of(root: &PathInfo, names: &[&str]) -> u64 {
+        root.get(&path(names)).unwrap().size(SizeMode::Apparent)
+    }
+
+    #[test]
+    fn insert_counts_one_link_per_file() {
+        let mut root = folder(vec![("old", folder(vec![])), ("new", folder(vec![]))]);
+        root.insert(
+            &path(&["old"]),
+            vec![("data".into(), file(1000, 2, 7, false))],
+        );
+        root.insert(
+            &path(&["new"]),
+            vec![("data".into(), file(1000, 2, 7, true))],
+        );
+        assert_eq!(root, linked_tree());
+        assert_eq!(size_of(&root, &[]), 1000);
+        assert_eq!(size_of(&root, &["new"]), 0);
+        assert_eq!(root.count(), Count { files: 1, dirs: 3 });
+    }
+
+    #[test]
+    fn replace_keeps_duplicate_links_uncounted() {
+        let mut root = linked_tree();
+        let rescanned = folder(vec![
+            ("data", file(1000, 2, 7, true)),
+            ("more", file(10, 1, 8, false)),
+        ]);
+        root.replace(&path(&["new"]), rescanned);
+        assert_eq!(size_of(&root, &[]), 1010);
+        assert_eq!(size_of(&root, &["new"]), 10);
+        assert_eq!(root.count(), Count { files: 2, dirs: 3 });
+    }
+
+    #[test]
+    fn shrink_updates_every_folder_along_the_path() {
+        let mut root = linked_tree();
+        let freed = Size {
+            apparent: 1000,
+            disk: 1000,
+        };
+        root.shrink(&path(&["old"]), freed, Count { files: 1, dirs: 0 });
+        assert_eq!(size_of(&root, &[]), 0);
+        assert_eq!(size_of(&root, &["old"]), 0);
+        assert_eq!(root.count(), Count { files: 0, dirs: 3 });
+    }
+
+    #[test]
+    fn removing_the_counted_link_counts_another() {
+        let mut root = linked_tree();
+        let unlinked = root.remove_entry(&[], &"old".into(), |info| (info.contribution(), true));
+        // The one link left needn't be checked against others anymore
+        assert_eq!(unlinked, vec![(1, 7)]);
+        assert_eq!(size_of(&root, &[]), 1000);
+        assert_eq!(size_of(&root, &["new"]), 1000);
+        assert_eq!(root.count(), Count { files: 1, dirs: 2 });
+        assert_eq!(
+            root.get(&path(&["new", "data"])),
+            Some(&file(1000, 1, 7, false))
+        );
+    }
+
+    #[test]
+    fn removing_a_duplicate_link_frees_nothing() {
+        let mut root = linked_tree();
+        let unlinked = root.remove_entry(&path(&["new"]), &"data".into(), |info| {
+            (info.contribution(), true)
+        });
+        assert_eq!(unlinked, vec![(1, 7)]);
+        assert_eq!(size_of(&root, &[]), 1000);
+        assert_eq!(root.count(), Count { files: 1, dirs: 3 });
+        assert_eq!(
+            root.get(&path(&["old", "data"])),
+            Some(&file(1000, 1, 7, false))
+        );
+    }
+
+    #[test]
+    fn removing_every_link_forgets_the_file() {
+        // With a third link outside the tree
+        let mut root = folder(vec![
+            ("old", folder(vec![("data", file(1000, 3, 7, false))])),
+            ("new", folder(vec![("data", file(1000, 3, 7, true))])),
+        ]);
+        let unlinked = root.remove_entry(&[], &"old".into(), |info| (info.contribution(), true));
+        assert!(unlinked.is_empty());
+        assert_eq!(
+            root.get(&path(&["new", "data"])),
+            Some(&file(1000, 2, 7, false))
+        );
+        let unlinked = root.remove_entry(&[], &"new".into(), |info| (info.contribution(), true));
+        assert_eq!(unlinked, vec![(1, 7)]);
+        assert_eq!(size_of(&root, &[]), 0);
+        assert_eq!(root.count(), Count { files: 0, dirs: 1 });
+    }
+
+    #[test]
+    fn confirming_removal_of_one_link_promises_nothing_freed() {
+        let root = linked_tree();
+        let summary = |paths| match Dialog::confirm(Action::Delete, paths, "", &root) {
+            Dialog::Confirm { summary, .. } => summary,
+            _ => unreachable!(),
+        };
+        assert_eq!(summary(vec![path(&["old"])]), "0 (0 apparent) in 2 items");
+        assert_eq!(
+            summary(vec![path(&["old"]), path(&["new"])]),
+            "1000 (1000 apparent) in 3 items"
+        );
+    }
+}
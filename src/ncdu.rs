@@ -0,0 +1,785 @@
+//! Reading and writing the JSON export format used by ncdu:
+//! https://dev.yorhel.nl/ncdu/jsonfmt
+use crate::{scan, sum_contents, PathInfo, ScanOptions, Size, SkipReason};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    io::{self, BufRead, Bytes, Read, Write},
+    iter::Peekable,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MAJOR_VERSION: u64 = 1;
+const MINOR_VERSION: u64 = 2;
+
+/// Writes `root`, an already complete tree of `dir` such as an imported one, in ncdu's export
+/// format.
+pub fn write_export(out: &mut impl Write, root: &PathInfo, dir: &Path) -> io::Result<()> {
+    write_header(out)?;
+    write_entry(out, dir.as_os_str(), root, None)?;
+    out.write_all(b"]\n")?;
+    out.flush()
+}
+
+/// Scans `dir` and writes it in ncdu's export format as it goes. Each folder is written once its
+/// own entries have been read, depth first, and removed from the scan's tree once everything
+/// below it has been written, so only the folders still being read or waiting their turn are
+/// held in memory rather than the whole tree.
+pub fn export_scan(out: &mut impl Write, dir: &Path, options: &ScanOptions) -> io::Result<()> {
+    let tree = Arc::new(Mutex::new(PathInfo::new_scanning_folder(
+        Size::default(),
+        0,
+        0,
+    )));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+    let scanner = {
+        let (dir, options) = (dir.to_path_buf(), options.clone());
+        let (tree, cancelled) = (Arc::clone(&tree), Arc::clone(&cancelled));
+        thread::spawn(move || scan(&dir, &options, &Arc::default(), &cancelled, &tree, Some(tx)))
+    };
+    let mut writer = ExportWriter {
+        tree: &tree,
+        listed: rx,
+        ready: HashMap::new(),
+    };
+    let result = writer.write(out, dir);
+    // There's no point reading what can't be written
+    if result.is_err() {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+    let _ = scanner.join();
+    result
+}
+
+/// Writes the tree of a running scan as the entries of its folders are read.
+struct ExportWriter<'a> {
+    tree: &'a Mutex<PathInfo>,
+    listed: mpsc::Receiver<(Vec<OsString>, Option<io::ErrorKind>)>,
+    /// The folders whose entries have been read but that haven't been written yet, with the
+    /// error encountered reading them, if any.
+    ready: HashMap<Vec<OsString>, Option<io::ErrorKind>>,
+}
+
+impl ExportWriter<'_> {
+    fn write(&mut self, out: &mut impl Write, dir: &Path) -> io::Result<()> {
+        write_header(out)?;
+        let (dev, folders) = self.open(out, &[], dir.as_os_str(), None)?;
+        // The folders being written, each with its device and the folders in it still to write
+        let mut stack = vec![(vec![], dev, folders)];
+        while let Some((path, dev, folders)) = stack.last_mut() {
+            let dev = *dev;
+            match folders.pop() {
+                Some(name) => {
+                    let mut child = path.clone();
+                    child.push(name.clone());
+                    out.write_all(b",\n")?;
+                    let (child_dev, child_folders) = self.open(out, &child, &name, Some(dev))?;
+                    stack.push((child, child_dev, child_folders));
+                }
+                None => {
+                    out.write_all(b"]")?;
+                    let (path, ..) = stack.pop().unwrap();
+                    // Everything below it has been written, so it's no longer needed
+                    if let Some((name, parent)) = path.split_last() {
+                        if let Ok(PathInfo::Folder { contents, .. }) =
+                            self.tree.lock().unwrap().join(parent)
+                        {
+                            contents.remove(name);
+                        }
+                    }
+                }
+            }
+        }
+        out.write_all(b"]\n")?;
+        out.flush()
+    }
+
+    /// Waits until the entries of the folder at `path`, named `name`, have been read, then opens
+    /// it and writes every entry in it other than folders, which are returned by name along with
+    /// the folder's device.
+    fn open(
+        &mut self,
+        out: &mut impl Write,
+        path: &[OsString],
+        name: &OsStr,
+        parent_dev: Option<u64>,
+    ) -> io::Result<(u64, Vec<OsString>)> {
+        let error = self.wait(path);
+        let mut entries = vec![];
+        let mut folders = vec![];
+        let (own_size, dev) = match self.tree.lock().unwrap().join(path) {
+            Ok(PathInfo::Folder {
+                size,
+                contents,
+                dev,
+                ..
+            }) => {
+                // Folders only record the size of the directory itself, not of their contents
+                let own_size = *size - sum_contents(contents);
+                // Folders stay in the tree, since their own entries may still be being read
+                for (name, info) in std::mem::take(contents) {
+                    match info {
+                        PathInfo::Folder { .. } => {
+                            folders.push(name.clone());
+                            contents.insert(name, info);
+                        }
+                        _ => entries.push((name, info)),
+                    }
+                }
+                (own_size, *dev)
+            }
+            _ => (Size::default(), 0),
+        };
+        open_folder(out, name, own_size, dev, parent_dev, error.is_some())?;
+        for (name, info) in &entries {
+            out.write_all(b",\n")?;
+            write_entry(out, name, info, Some(dev))?;
+        }
+        Ok((dev, folders))
+    }
+
+    /// Waits until the entries of the folder at `path` have been read, returning the error
+    /// encountered reading them, if any.
+    fn wait(&mut self, path: &[OsString]) -> Option<io::ErrorKind> {
+        loop {
+            if let Some(error) = self.ready.remove(path) {
+                return error;
+            }
+            match self.listed.recv() {
+                Ok((listed, error)) => {
+                    self.ready.insert(listed, error);
+                }
+                // The scan ended without reading it, which happens when the directory it started
+                // from can't be read
+                Err(_) => {
+                    return match self.tree.lock().unwrap().get(path) {
+                        Some(PathInfo::Folder { error, .. }) => *error,
+                        _ => Some(io::ErrorKind::NotFound),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Writes the version and metadata that start an export, up to its root folder.
+fn write_header(out: &mut impl Write) -> io::Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    write!(
+        out,
+        "[{},{},{{\"progname\":\"rsdu\",\"progver\":\"{}\",\"timestamp\":{}}}",
+        MAJOR_VERSION,
+        MINOR_VERSION,
+        env!("CARGO_PKG_VERSION"),
+        timestamp
+    )?;
+    out.write_all(b",\n")
+}
+
+/// Opens a folder's array and writes the object describing the directory itself, which its
+/// entries follow.
+fn open_folder(
+    out: &mut impl Write,
+    name: &OsStr,
+    own_size: Size,
+    dev: u64,
+    parent_dev: Option<u64>,
+    error: bool,
+) -> io::Result<()> {
+    out.write_all(b"[")?;
+    write_name(out, name)?;
+    write!(
+        out,
+        ",\"asize\":{},\"dsize\":{}",
+        own_size.apparent, own_size.disk
+    )?;
+    if parent_dev != Some(dev) {
+        write!(out, ",\"dev\":{}", dev)?;
+    }
+    if error {
+        out.write_all(b",\"read_error\":true")?;
+    }
+    out.write_all(b"}")
+}
+
+fn write_entry(
+    out: &mut impl Write,
+    name: &OsStr,
+    info: &PathInfo,
+    parent_dev: Option<u64>,
+) -> io::Result<()> {
+    match info {
+        PathInfo::Folder {
+            size,
+            contents,
+            dev,
+            error,
+            ..
+        } => {
+            // Folders only record the size of the directory itself, not of their contents
+            let own_size = *size - sum_contents(contents);
+            open_folder(out, name, own_size, *dev, parent_dev, error.is_some())?;
+            for (name, info) in contents {
+                out.write_all(b",\n")?;
+                write_entry(out, name, info, Some(*dev))?;
+            }
+            out.write_all(b"]")
+        }
+        PathInfo::File {
+            size,
+            links,
+            ino,
+            mtime,
+            ..
+        } => {
+            write_name(out, name)?;
+            write!(
+                out,
+                ",\"asize\":{},\"dsize\":{},\"ino\":{}",
+                size.apparent, size.disk, ino
+            )?;
+            // Folders aren't given one, since theirs covers everything below them too
+            if *mtime > 0 {
+                write!(out, ",\"mtime\":{}", mtime)?;
+            }
+            if *links > 1 {
+                write!(out, ",\"hlnkc\":true,\"nlink\":{}", links)?;
+            }
+            out.write_all(b"}")
+        }
+        PathInfo::Error(..) => {
+            write_name(out, name)?;
+            out.write_all(b",\"read_error\":true}")
+        }
+        PathInfo::Skipped { reason, dir } => {
+            if *dir {
+                out.write_all(b"[")?;
+            }
+            write_name(out, name)?;
+            let excluded = match reason {
+                SkipReason::OtherFilesystem => "otherfs",
+                SkipReason::KernelFilesystem => "kernfs",
+                SkipReason::Pattern | SkipReason::CacheDir => "pattern",
+            };
+            write!(out, ",\"excluded\":\"{}\"}}", excluded)?;
+            if *dir {
+                out.write_all(b"]")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Opens an entry's object and writes its name. Like ncdu, bytes that aren't valid UTF-8 are
+/// written as-is.
+fn write_name(out: &mut impl Write, name: &OsStr) -> io::Result<()> {
+    out.write_all(b"{\"name\":\"")?;
+    for &byte in name.as_bytes() {
+        match byte {
+            b'"' => out.write_all(b"\\\"")?,
+            b'\\' => out.write_all(b"\\\\")?,
+            0..=0x1f => write!(out, "\\u{:04x}", byte)?,
+            _ => out.write_all(&[byte])?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+/// Reads an export written by ncdu or by [`write_export`], returning the directory that was
+/// scanned along with its tree. The input is parsed as it's read, so only the tree is kept in
+/// memory.
+pub fn read_export(input: impl BufRead) -> io::Result<(PathBuf, PathInfo)> {
+    let mut parser = Parser {
+        bytes: input.bytes().peekable(),
+        offset: 0,
+        inodes: HashSet::new(),
+    };
+    parser.expect(b'[')?;
+    let major = parser.number()?;
+    if major != MAJOR_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported export format version {}",
+            major
+        )));
+    }
+    parser.expect(b',')?;
+    // Minor versions only add fields, which are ignored if unknown
+    parser.number()?;
+    parser.expect(b',')?;
+    parser.skip_value()?;
+    parser.expect(b',')?;
+    if parser.peek()? != b'[' {
+        return Err(parser.error("the exported root isn't a directory"));
+    }
+    let (name, root) = parser.entry(0)?;
+    parser.expect(b']')?;
+    Ok((PathBuf::from(name), root))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The fields of an entry's info object that are used here.
+#[derive(Default)]
+struct Info {
+    name: Vec<u8>,
+    size: Size,
+    dev: Option<u64>,
+    ino: u64,
+    mtime: u64,
+    nlink: Option<u64>,
+    hard_link: bool,
+    read_error: bool,
+    excluded: Option<SkipReason>,
+}
+
+struct Parser<R: Read> {
+    bytes: Peekable<Bytes<R>>,
+    offset: usize,
+    /// Hard linked inodes seen so far, so that only the first link is counted, as when scanning.
+    inodes: HashSet<(u64, u64)>,
+}
+
+impl<R: Read> Parser<R> {
+    fn error(&self, message: &str) -> io::Error {
+        invalid_data(format!("{} at byte {}", message, self.offset))
+    }
+
+    /// The next byte that isn't whitespace, without consuming it.
+    fn peek(&mut self) -> io::Result<u8> {
+        loop {
+            match self.bytes.peek() {
+                Some(Ok(b' ')) | Some(Ok(b'\t')) | Some(Ok(b'\n')) | Some(Ok(b'\r')) => {
+                    self.bytes.next();
+                    self.offset += 1;
+                }
+                Some(Ok(byte)) => return Ok(*byte),
+                Some(Err(_)) => return Err(self.bytes.next().unwrap().unwrap_err()),
+                None => return Err(self.error("unexpected end of input")),
+            }
+        }
+    }
+
+    fn next(&mut self) -> io::Result<u8> {
+        let byte = self.peek()?;
+        self.bytes.next();
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    /// Like [`Parser::next`], but doesn't skip whitespace, for reading inside strings.
+    fn next_raw(&mut self) -> io::Result<u8> {
+        match self.bytes.next() {
+            Some(byte) => {
+                self.offset += 1;
+                byte
+            }
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> io::Result<()> {
+        if self.next()? == expected {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected as char)))
+        }
+    }
+
+    /// Reads a directory array or a file object, named by its info object.
+    fn entry(&mut self, parent_dev: u64) -> io::Result<(OsString, PathInfo)> {
+        if self.peek()? != b'[' {
+            let info = self.info()?;
+            let dev = info.dev.unwrap_or(parent_dev);
+            let name = OsString::from_vec(info.name);
+            let path_info = if let Some(reason) = info.excluded {
+                PathInfo::Skipped { reason, dir: false }
+            } else if info.read_error {
+                PathInfo::Error(io::ErrorKind::Other)
+            } else {
+                let links = info.nlink.unwrap_or(if info.hard_link { 2 } else { 1 });
+                PathInfo::File {
+                    size: info.size,
+                    links,
+                    ino: info.ino,
+                    mtime: info.mtime as i64,
+                    duplicate: info.hard_link && !self.inodes.insert((dev, info.ino)),
+                }
+            };
+            return Ok((name, path_info));
+        }
+
+        self.expect(b'[')?;
+        let info = self.info()?;
+        let dev = info.dev.unwrap_or(parent_dev);
+        let mut contents = BTreeMap::new();
+        loop {
+            match self.next()? {
+                b',' => {
+                    let (name, path_info) = self.entry(dev)?;
+                    contents.insert(name, path_info);
+                }
+                b']' => break,
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+        let name = OsString::from_vec(info.name);
+        let path_info = match info.excluded {
+            Some(reason) => PathInfo::Skipped { reason, dir: true },
+            None => PathInfo::new_folder(
+                info.size,
+                info.mtime as i64,
+                dev,
+                contents,
+                if info.read_error {
+                    Some(io::ErrorKind::Other)
+                } else {
+                    None
+                },
+            ),
+        };
+        Ok((name, path_info))
+    }
+
+    fn info(&mut self) -> io::Result<Info> {
+        let mut info = Info::default();
+        let mut named = false;
+        self.expect(b'{')?;
+        if self.peek()? == b'}' {
+            self.next()?;
+        } else {
+            loop {
+                let key = self.string()?;
+                self.expect(b':')?;
+                match key.as_slice() {
+                    b"name" => {
+                        info.name = self.string()?;
+                        named = true;
+                    }
+                    b"asize" => info.size.apparent = self.number()?,
+                    b"dsize" => info.size.disk = self.number()?,
+                    b"dev" => info.dev = Some(self.number()?),
+                    b"ino" => info.ino = self.number()?,
+                    b"mtime" => info.mtime = self.number()?,
+                    b"nlink" => info.nlink = Some(self.number()?),
+                    b"hlnkc" => info.hard_link = self.boolean()?,
+                    b"read_error" => info.read_error = self.boolean()?,
+                    b"excluded" => {
+                        info.excluded = Some(match self.string()?.as_slice() {
+                            b"otherfs" | b"othfs" => SkipReason::OtherFilesystem,
+                            b"kernfs" => SkipReason::KernelFilesystem,
+                            _ => SkipReason::Pattern,
+                        })
+                    }
+                    _ => self.skip_value()?,
+                }
+                match self.next()? {
+                    b',' => {}
+                    b'}' => break,
+                    _ => return Err(self.error("expected ',' or '}'")),
+                }
+            }
+        }
+        if !named {
+            return Err(self.error("entry without a name"));
+        }
+        Ok(info)
+    }
+
+    /// Reads a string's bytes. Invalid UTF-8 is passed through, since that's how ncdu exports
+    /// names that aren't UTF-8.
+    fn string(&mut self) -> io::Result<Vec<u8>> {
+        self.expect(b'"')?;
+        let mut string = vec![];
+        loop {
+            match self.next_raw()? {
+                b'"' => return Ok(string),
+                b'\\' => {
+                    let escaped = match self.next_raw()? {
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let high = self.hex_escape()?;
+                            if (0xd800..0xdc00).contains(&high) {
+                                // A surrogate pair, which must be followed by its second half
+                                if self.next_raw()? != b'\\' || self.next_raw()? != b'u' {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                let low = self.hex_escape()?;
+                                let code = 0x10000 + ((high - 0xd800) << 10) + (low & 0x3ff);
+                                std::char::from_u32(code)
+                                    .ok_or_else(|| self.error("invalid surrogate pair"))?
+                            } else {
+                                std::char::from_u32(high).unwrap_or('\u{fffd}')
+                            }
+                        }
+                        byte => byte as char,
+                    };
+                    let mut buffer = [0; 4];
+                    string.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte => string.push(byte),
+            }
+        }
+    }
+
+    fn hex_escape(&mut self) -> io::Result<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = (self.next_raw()? as char)
+                .to_digit(16)
+                .ok_or_else(|| self.error("invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    /// Reads a non-negative integer, which is all the fields used here hold.
+    fn number(&mut self) -> io::Result<u64> {
+        let mut number: u64 = 0;
+        if !self.peek()?.is_ascii_digit() {
+            return Err(self.error("expected a number"));
+        }
+        while let Some(Ok(byte)) = self.bytes.peek() {
+            if !byte.is_ascii_digit() {
+                break;
+            }
+            number = number
+                .checked_mul(10)
+                .and_then(|number| number.checked_add(u64::from(byte - b'0')))
+                .ok_or_else(|| self.error("number out of range"))?;
+            self.bytes.next();
+            self.offset += 1;
+        }
+        Ok(number)
+    }
+
+    fn boolean(&mut self) -> io::Result<bool> {
+        match self.peek()? {
+            b't' => self.literal(b"true").map(|_| true),
+            _ => self.literal(b"false").map(|_| false),
+        }
+    }
+
+    fn literal(&mut self, literal: &[u8]) -> io::Result<()> {
+        self.peek()?;
+        for &expected in literal {
+            if self.next_raw()? != expected {
+                return Err(self.error("invalid literal"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips over any value, such as the metadata object or fields added by newer versions.
+    fn skip_value(&mut self) -> io::Result<()> {
+        match self.peek()? {
+            b'"' => self.string().map(|_| ()),
+            b't' => self.literal(b"true"),
+            b'f' => self.literal(b"false"),
+            b'n' => self.literal(b"null"),
+            open @ b'[' | open @ b'{' => {
+                let close = if open == b'[' { b']' } else { b'}' };
+                self.next()?;
+                if self.peek()? == close {
+                    self.next()?;
+                    return Ok(());
+                }
+                loop {
+                    if open == b'{' {
+                        self.string()?;
+                        self.expect(b':')?;
+                    }
+                    self.skip_value()?;
+                    match self.next()? {
+                        b',' => {}
+                        byte if byte == close => return Ok(()),
+                        _ => return Err(self.error("unterminated array or object")),
+                    }
+                }
+            }
+            _ => {
+                // Numbers, including any fractions or exponents
+                self.peek()?;
+                let mut empty = true;
+                while let Some(Ok(byte)) = self.bytes.peek() {
+                    if !(byte.is_ascii_digit() || b"+-.eE".contains(byte)) {
+                        break;
+                    }
+                    empty = false;
+                    self.bytes.next();
+                    self.offset += 1;
+                }
+                if empty {
+                    return Err(self.error("unexpected character"));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn file(apparent: u64, ino: u64, links: u64, duplicate: bool) -> PathInfo {
+        PathInfo::File {
+            size: Size {
+                apparent,
+                disk: 4096,
+            },
+            links,
+            ino,
+            mtime: 1_600_000_000,
+            duplicate,
+        }
+    }
+
+    fn folder(dev: u64, entries: Vec<(OsString, PathInfo)>, error: bool) -> PathInfo {
+        let own_size = Size {
+            apparent: 4096,
+            disk: 4096,
+        };
+        let error = if error {
+            Some(io::ErrorKind::Other)
+        } else {
+            None
+        };
+        PathInfo::new_folder(own_size, 0, dev, entries.into_iter().collect(), error)
+    }
+
+    fn round_trip(root: &PathInfo, dir: &Path) -> (PathBuf, PathInfo) {
+        let mut out = vec![];
+        write_export(&mut out, root, dir).unwrap();
+        read_export(&out[..]).unwrap()
+    }
+
+    #[test]
+    fn round_trip_keeps_tree() {
+        let root = folder(
+            1,
+            vec![
+                ("a".into(), file(100, 7, 2, false)),
+                (
+                    "b".into(),
+                    folder(
+                        1,
+                        vec![
+                            ("link".into(), file(100, 7, 2, true)),
+                            ("gone".into(), PathInfo::Error(io::ErrorKind::Other)),
+                        ],
+                        true,
+                    ),
+                ),
+                (
+                    "excluded".into(),
+                    PathInfo::Skipped {
+                        reason: SkipReason::Pattern,
+                        dir: true,
+                    },
+                ),
+                (
+                    "mnt".into(),
+                    folder(2, vec![("f".into(), file(5, 7, 1, false))], false),
+                ),
+                (
+                    "other".into(),
+                    PathInfo::Skipped {
+                        reason: SkipReason::OtherFilesystem,
+                        dir: true,
+                    },
+                ),
+                ("quote\"back\\slash\ttab".into(), file(1, 8, 1, false)),
+                ("日本".into(), file(2, 9, 1, false)),
+                (
+                    OsString::from_vec(b"caf\xe9".to_vec()),
+                    file(3, 10, 1, false),
+                ),
+            ],
+            false,
+        );
+        let (dir, imported) = round_trip(&root, Path::new("/scanned"));
+        assert_eq!(dir, Path::new("/scanned"));
+        assert_eq!(imported, root);
+        // The second link to the same inode doesn't count towards the total
+        assert_eq!(
+            imported.size(crate::SizeMode::Apparent),
+            3 * 4096 + 100 + 5 + 6
+        );
+    }
+
+    #[test]
+    fn export_scan_matches_scanned_tree() {
+        let dir = std::env::temp_dir().join(format!("rsdu-export-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        fs::create_dir_all(dir.join("skipped")).unwrap();
+        fs::write(dir.join("a"), [0; 10]).unwrap();
+        fs::write(dir.join("sub/b"), [0; 5000]).unwrap();
+        fs::write(dir.join("skipped/c"), [0; 1]).unwrap();
+        let mut options = ScanOptions {
+            exclude: vec!["skipped".to_string()],
+            ..ScanOptions::default()
+        };
+        options.compile_excludes().unwrap();
+
+        let mut streamed = vec![];
+        export_scan(&mut streamed, &dir, &options).unwrap();
+        let scanned =
+            crate::get_wrapped_contents(&dir, &options, &Default::default(), &Default::default());
+        let mut written = vec![];
+        write_export(&mut written, &scanned, &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            read_export(&streamed[..]).unwrap(),
+            read_export(&written[..]).unwrap()
+        );
+    }
+
+    #[test]
+    fn reads_escapes() {
+        let input = br#"[1,0,{"progname":"ncdu"},
+            [{"name":"/d\u00e9j\u00E0"},{"name":"\ud83d\ude00\n\/","asize":1}]]"#;
+        let (dir, root) = read_export(&input[..]).unwrap();
+        assert_eq!(dir, Path::new("/déjà"));
+        let name = root.contents().unwrap().keys().next().unwrap();
+        assert_eq!(name, "😀\n/");
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let inputs: [&[u8]; 9] = [
+            b"",
+            b"[",
+            b"{}",
+            br#"[2,0,{},[{"name":"/"}]]"#,
+            br#"[1,0,{},{"name":"/"}]"#,
+            br#"[1,0,{},[{"name":"/"},]]"#,
+            br#"[1,0,{},[{"name":"/"}]"#,
+            br#"[1,0,{},[{"name":"/","asize":1x}]]"#,
+            br#"[1,0,{},[{"name":"\ud83d"}]]"#,
+        ];
+        for input in inputs {
+            assert!(
+                read_export(input).is_err(),
+                "{}",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Printing a scanned tree as plain text, for use in scripts and logs.
+use crate::{count_column, pad_and_prettify_bytes, sort::Sort, PathInfo, Size, SizeMode};
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+/// How the tree is printed, and limits on how much of it is.
+pub struct ReportOptions {
+    pub mode: SizeMode,
+    pub sort: Sort,
+    /// Whether each folder's number of items is printed after its size.
+    pub show_count: bool,
+    /// The deepest level of entries to print, where the contents of `dir` are level one.
+    pub depth: Option<usize>,
+    /// The number of entries to print first in each folder.
+    pub top: Option<usize>,
+}
+
+/// Writes `root`, which was scanned from `dir`, as an indented tree with each folder's entries
+/// sorted by `options.sort`. Entries left out by `options.top` are summarized on a single line.
+pub fn write_report(
+    out: &mut impl Write,
+    root: &PathInfo,
+    dir: &Path,
+    options: &ReportOptions,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}{}{} {}",
+        root.flag(),
+        pad_and_prettify_bytes(&root.size(options.mode)),
+        count(root, options),
+        dir.display()
+    )?;
+    write_contents(out, root, 1, options)
+}
+
+fn write_contents(
+    out: &mut impl Write,
+    info: &PathInfo,
+    level: usize,
+    options: &ReportOptions,
+) -> io::Result<()> {
+    if options.depth.is_some_and(|depth| level > depth) {
+        return Ok(());
+    }
+    let sorted = match info.sorted(options.mode, options.sort) {
+        Ok(sorted) => sorted,
+        Err(_) => return Ok(()),
+    };
+    let shown = options.top.unwrap_or(sorted.len()).min(sorted.len());
+    let indent = "  ".repeat(level);
+    for (name, child) in &sorted[..shown] {
+        let size = match child {
+            PathInfo::Skipped { reason, .. } if reason.is_exclusion() => {
+                " ".repeat(8) + &count(child, options)
+            }
+            _ => pad_and_prettify_bytes(&child.size(options.mode)) + &count(child, options),
+        };
+        writeln!(
+            out,
+            "{}{} {}{}{}",
+            child.flag(),
+            size,
+            indent,
+            name.to_string_lossy(),
+            child.suffix()
+        )?;
+        write_contents(out, child, level + 1, options)?;
+    }
+    if shown < sorted.len() {
+        let rest = sorted[shown..]
+            .iter()
+            .fold(Size::default(), |acc, (_, child)| {
+                acc + child.contribution()
+            });
+        writeln!(
+            out,
+            " {}{} {}... {} more",
+            pad_and_prettify_bytes(&rest.get(options.mode)),
+            if options.show_count {
+                " ".repeat(10)
+            } else {
+                String::new()
+            },
+            indent,
+            sorted.len() - shown
+        )?;
+    }
+    Ok(())
+}
+
+fn count(info: &PathInfo, options: &ReportOptions) -> String {
+    if options.show_count {
+        count_column(info)
+    } else {
+        String::new()
+    }
+}
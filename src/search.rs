@@ -0,0 +1,80 @@
+//! Searching the entries of the current folder by name.
+use crate::PathInfo;
+use std::ffi::{OsStr, OsString};
+
+/// A search of the current folder's entries by name.
+#[derive(Debug, Default)]
+pub struct Search {
+    pub query: String,
+    /// Whether the query is still being typed.
+    pub editing: bool,
+    /// Whether entries that don't match are hidden.
+    pub filter: bool,
+    /// Whether sizes and bars are relative to the entries shown rather than the whole folder
+    /// while filtering.
+    pub relative: bool,
+}
+
+impl Search {
+    /// Forgets the query, keeping the filter settings for the next one.
+    pub fn clear(&mut self) {
+        self.query.clear();
+        self.editing = false;
+    }
+
+    /// Whether entries are being hidden by this search.
+    pub fn is_filtering(&self) -> bool {
+        self.filter && !self.query.is_empty()
+    }
+
+    /// Whether `name` contains the query, ignoring case unless the query has uppercase letters.
+    pub fn matches(&self, name: &OsStr) -> bool {
+        let name = name.to_string_lossy();
+        if self.query.chars().any(char::is_uppercase) {
+            name.contains(&self.query)
+        } else {
+            name.to_lowercase().contains(&self.query.to_lowercase())
+        }
+    }
+
+    /// Leaves out the entries that don't match, if filtering.
+    pub fn apply<'a>(
+        &self,
+        entries: Vec<(&'a OsString, &'a PathInfo)>,
+    ) -> Vec<(&'a OsString, &'a PathInfo)> {
+        if self.is_filtering() {
+            entries
+                .into_iter()
+                .filter(|(name, _)| self.matches(name))
+                .collect()
+        } else {
+            entries
+        }
+    }
+
+    /// The index of the nearest match to `from` in `entries` in the given direction, wrapping
+    /// around, which is `from` itself if it matches and `inclusive` is set.
+    pub fn find(
+        &self,
+        entries: &[(&OsString, &PathInfo)],
+        from: usize,
+        forward: bool,
+        inclusive: bool,
+    ) -> Option<usize> {
+        let len = entries.len();
+        if self.query.is_empty() || len == 0 {
+            return None;
+        }
+        let from = from.min(len - 1);
+        let start = if inclusive { 0 } else { 1 };
+        (start..start + len)
+            .map(|offset| {
+                if forward {
+                    (from + offset) % len
+                } else {
+                    (from + 2 * len - offset) % len
+                }
+            })
+            .find(|&i| self.matches(entries[i].0))
+    }
+}
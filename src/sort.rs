@@ -0,0 +1,198 @@
+//! The orders that a folder's entries can be listed in.
+use std::{cmp::Ordering, ffi::OsStr, iter::Peekable, path::Path, str::Chars};
+
+/// What the entries of a folder are compared by.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum SortKey {
+    /// Size, largest first
+    Size,
+    /// Name, comparing runs of digits as numbers
+    Name,
+    /// Number of entries inside, most first
+    Count,
+    /// Latest modification time of anything inside, newest first
+    Mtime,
+    /// Extension, then name
+    Extension,
+}
+
+impl SortKey {
+    /// Whether this key lists the largest, most or newest entries first unless reversed.
+    fn descending_by_default(self) -> bool {
+        matches!(self, SortKey::Size | SortKey::Count | SortKey::Mtime)
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SortKey::Size => "size",
+            SortKey::Name => "name",
+            SortKey::Count => "count",
+            SortKey::Mtime => "modification time",
+            SortKey::Extension => "extension",
+        }
+    }
+}
+
+/// How the entries of a folder are ordered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sort {
+    pub key: SortKey,
+    pub descending: bool,
+    /// Whether folders are listed before everything else.
+    pub dirs_first: bool,
+}
+
+impl Sort {
+    /// Sorts by `key` in its usual direction, or the opposite one if `reverse` is set.
+    pub fn new(key: SortKey, reverse: bool, dirs_first: bool) -> Sort {
+        Sort {
+            key,
+            descending: key.descending_by_default() != reverse,
+            dirs_first,
+        }
+    }
+
+    /// Sorts by `key` instead, reversing the direction if it's already the key being sorted by.
+    pub fn by(self, key: SortKey) -> Sort {
+        if key == self.key {
+            Sort {
+                descending: !self.descending,
+                ..self
+            }
+        } else {
+            Sort::new(key, false, self.dirs_first)
+        }
+    }
+
+    pub fn label(self) -> String {
+        format!(
+            "by {}, {}{}",
+            self.key.label(),
+            if self.descending {
+                "descending"
+            } else {
+                "ascending"
+            },
+            if self.dirs_first {
+                ", folders first"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+/// Compares names the way people tend to, ignoring case and comparing runs of digits by their
+/// value, so that `file9` comes before `file10`.
+pub fn natural_cmp(a: &OsStr, b: &OsStr) -> Ordering {
+    let (a_string, b_string) = (a.to_string_lossy(), b.to_string_lossy());
+    let mut a_chars = a_string.chars().peekable();
+    let mut b_chars = b_string.chars().peekable();
+    loop {
+        let ordering = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => break,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_digits = take_digits(&mut a_chars);
+                let y_digits = take_digits(&mut b_chars);
+                let x_value = x_digits.trim_start_matches('0');
+                let y_value = y_digits.trim_start_matches('0');
+                x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value))
+            }
+            (Some(&x), Some(&y)) => {
+                a_chars.next();
+                b_chars.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    // Names differing only in case or leading zeros still need a consistent order
+    a.cmp(b)
+}
+
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        digits.push(c);
+        chars.next();
+    }
+    digits
+}
+
+/// The extension of `name` in lowercase, or an empty string if it doesn't have one.
+pub fn extension(name: &OsStr) -> String {
+    Path::new(name)
+        .extension()
+        .map_or(String::new(), |extension| {
+            extension.to_string_lossy().to_lowercase()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<_> = names.iter().map(OsStr::new).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+            .iter()
+            .map(|name| name.to_string_lossy().into())
+            .collect()
+    }
+
+    #[test]
+    fn compares_digits_by_value() {
+        assert_eq!(
+            sorted(&["file10", "file9", "file1", "file100", "file09b"]),
+            ["file1", "file9", "file09b", "file10", "file100"]
+        );
+        assert_eq!(
+            sorted(&["v1.10", "v1.9", "v1.9.1"]),
+            ["v1.9", "v1.9.1", "v1.10"]
+        );
+        // Longer than any integer type
+        assert_eq!(
+            sorted(&[
+                "123456789012345678901234567891",
+                "123456789012345678901234567890"
+            ]),
+            [
+                "123456789012345678901234567890",
+                "123456789012345678901234567891"
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_case_but_stays_consistent() {
+        assert_eq!(sorted(&["b", "A", "a", "B"]), ["A", "a", "B", "b"]);
+        assert_eq!(sorted(&["x01", "x1", "x001"]), ["x001", "x01", "x1"]);
+        assert_eq!(
+            natural_cmp(OsStr::new("same"), OsStr::new("same")),
+            Ordering::Equal
+        );
+        assert_eq!(
+            natural_cmp(OsStr::new("a"), OsStr::new("a1")),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn takes_extension_in_lowercase() {
+        assert_eq!(extension(OsStr::new("Photo.JPG")), "jpg");
+        assert_eq!(extension(OsStr::new("archive.tar.gz")), "gz");
+        assert_eq!(extension(OsStr::new(".bashrc")), "");
+        assert_eq!(extension(OsStr::new("Makefile")), "");
+    }
+}
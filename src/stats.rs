@@ -0,0 +1,236 @@
+//! Breaking down the space used in a folder by file extension and by kind of file.
+use crate::{
+    pad_and_prettify_bytes, prettify_bytes, size_bar, sort::extension, PathInfo, Size, SizeMode,
+};
+use std::{cmp::Reverse, collections::HashMap, ffi::OsString, hash::Hash, path::Path};
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, List, ListItem, ListState},
+    Frame,
+};
+
+/// A coarse kind of file, going by its extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    Images,
+    Video,
+    Audio,
+    Archives,
+    Source,
+    Binaries,
+    Logs,
+    Other,
+}
+
+impl Category {
+    /// The category of files with `extension`, which is in lowercase as [`extension`] gives it.
+    pub fn of(extension: &str) -> Category {
+        match extension {
+            "png" | "jpg" | "jpeg" | "gif" | "bmp" | "tif" | "tiff" | "webp" | "svg" | "ico"
+            | "heic" | "raw" | "cr2" | "nef" | "psd" | "xcf" => Category::Images,
+            "mp4" | "mkv" | "avi" | "mov" | "wmv" | "flv" | "webm" | "m4v" | "mpg" | "mpeg"
+            | "vob" => Category::Video,
+            "mp3" | "flac" | "wav" | "ogg" | "opus" | "m4a" | "aac" | "wma" => Category::Audio,
+            "zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "7z" | "rar" | "lz4" | "iso"
+            | "deb" | "rpm" | "dmg" | "jar" | "whl" | "crate" => Category::Archives,
+            "rs" | "c" | "h" | "cc" | "cpp" | "hpp" | "go" | "py" | "js" | "jsx" | "ts" | "tsx"
+            | "java" | "kt" | "rb" | "php" | "cs" | "swift" | "sh" | "pl" | "lua" | "hs" | "ml"
+            | "scala" | "zig" | "toml" | "json" | "yaml" | "yml" | "xml" | "html" | "css"
+            | "md" | "sql" => Category::Source,
+            "o" | "a" | "so" | "dll" | "exe" | "bin" | "rlib" | "rmeta" | "class" | "pyc"
+            | "wasm" | "lib" | "obj" | "dylib" => Category::Binaries,
+            "log" | "out" | "err" | "journal" => Category::Logs,
+            _ => Category::Other,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Category::Images => "images",
+            Category::Video => "video",
+            Category::Audio => "audio",
+            Category::Archives => "archives",
+            Category::Source => "source",
+            Category::Binaries => "binaries",
+            Category::Logs => "logs",
+            Category::Other => "other",
+        }
+    }
+}
+
+/// The space taken by a group of files, and how many there are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub size: Size,
+    pub files: u64,
+}
+
+/// The files in a folder grouped by category and by extension.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub categories: HashMap<Category, Usage>,
+    /// Files without an extension are grouped under an empty one.
+    pub extensions: HashMap<String, Usage>,
+}
+
+impl Stats {
+    /// Groups every file below `info`, leaving out duplicate hard links like folder sizes do.
+    pub fn collect(info: &PathInfo) -> Stats {
+        let mut stats = Stats::default();
+        stats.add(info);
+        stats
+    }
+
+    fn add(&mut self, info: &PathInfo) {
+        if let Ok(contents) = info.contents() {
+            for (name, child) in contents {
+                if let PathInfo::File {
+                    duplicate: false, ..
+                } = child
+                {
+                    let extension = extension(name);
+                    for usage in [
+                        self.categories.entry(Category::of(&extension)).or_default(),
+                        self.extensions.entry(extension).or_default(),
+                    ] {
+                        usage.size += child.contribution();
+                        usage.files += 1;
+                    }
+                }
+                self.add(child);
+            }
+        }
+    }
+
+    /// The combined size of every file grouped.
+    pub fn total(&self) -> Size {
+        self.categories
+            .values()
+            .fold(Size::default(), |acc, usage| acc + usage.size)
+    }
+}
+
+/// The groups in `usages`, largest first.
+pub fn largest_first<K: Clone + Ord + Hash>(
+    usages: &HashMap<K, Usage>,
+    mode: SizeMode,
+) -> Vec<(K, Usage)> {
+    let mut sorted: Vec<_> = usages
+        .iter()
+        .map(|(key, usage)| (key.clone(), *usage))
+        .collect();
+    // Ties are broken by key so the order doesn't change between redraws
+    sorted.sort_by_key(|(key, usage)| (Reverse(usage.size.get(mode)), key.clone()));
+    sorted
+}
+
+/// What the stats view shows. The stats are only collected again when asked to, since that
+/// takes a walk over the whole tree.
+#[derive(Default)]
+pub struct StatsView {
+    pub stats: Stats,
+    /// Whether only the current folder is counted rather than the whole tree.
+    pub this_folder: bool,
+    /// The cursor in the list of extensions.
+    pub state: ListState,
+}
+
+impl StatsView {
+    /// Counts the files in `root`, or only in the folder at `dir` if that's what's shown.
+    pub fn collect(&mut self, root: &PathInfo, dir: &[OsString]) {
+        let counted = if self.this_folder {
+            root.get(dir).unwrap()
+        } else {
+            root
+        };
+        self.stats = Stats::collect(counted);
+    }
+
+    /// Draws the stats in `area`, by kind on the left and by extension on the right, where
+    /// `folder` is the current folder on disk and `scanning` says whether they're incomplete.
+    pub fn draw<B: Backend>(
+        &mut self,
+        f: &mut Frame<B>,
+        area: Rect,
+        mode: SizeMode,
+        folder: &Path,
+        scanning: bool,
+    ) {
+        let total = self.stats.total().get(mode);
+        let row = |size: u64, files: u64, label: &str| {
+            ListItem::new(Spans::from(Span::raw(
+                " ".to_string()
+                    + &pad_and_prettify_bytes(&size)
+                    + &size_bar(&size, &total)
+                    + &format!("{:>9}", files)
+                    + "  "
+                    + label,
+            )))
+        };
+        let categories: Vec<_> = largest_first(&self.stats.categories, mode)
+            .into_iter()
+            .map(|(category, usage)| row(usage.size.get(mode), usage.files, category.label()))
+            .collect();
+        let extensions: Vec<_> = largest_first(&self.stats.extensions, mode)
+            .into_iter()
+            .map(|(extension, usage)| {
+                let label = if extension.is_empty() {
+                    "(none)".to_string()
+                } else {
+                    ".".to_string() + &extension
+                };
+                row(usage.size.get(mode), usage.files, &label)
+            })
+            .collect();
+        let extension_count = extensions.len();
+        let halves = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(area);
+        f.render_widget(
+            List::new(categories).block(
+                Block::default()
+                    .title(format!(
+                        " {}{}: {} by kind{} ",
+                        if self.this_folder {
+                            folder.to_string_lossy().into_owned()
+                        } else {
+                            "Whole tree".to_string()
+                        },
+                        // Not counted again until the scan is done
+                        if scanning { " so far" } else { "" },
+                        prettify_bytes(&total),
+                        if scanning { " (U: update)" } else { "" }
+                    ))
+                    .borders(Borders::ALL),
+            ),
+            halves[0],
+        );
+        let extensions = List::new(extensions)
+            .block(
+                Block::default()
+                    .title(format!(
+                        " By extension (w: {}, h: back) ",
+                        if self.this_folder {
+                            "whole tree"
+                        } else {
+                            "this folder"
+                        }
+                    ))
+                    .borders(Borders::ALL),
+            )
+            .highlight_style(
+                Style::default()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::BOLD),
+            );
+        if let Some(selected) = self.state.selected() {
+            self.state
+                .select(Some(selected.min(extension_count.saturating_sub(1))));
+        }
+        f.render_stateful_widget(extensions, halves[1], &mut self.state);
+    }
+}
@@ -0,0 +1,188 @@
+//! Moving files to the trash as described by the FreeDesktop.org trash specification:
+//! https://specifications.freedesktop.org/trash-spec/trashspec-latest.html
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fs::{self, DirBuilder, OpenOptions},
+    io::{self, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+/// Moves the file or directory at `path` into the appropriate trash directory, writing a
+/// `.trashinfo` file so that it can be restored later.
+pub fn trash(path: &Path) -> io::Result<()> {
+    let path = absolute(path)?;
+    let dev = fs::symlink_metadata(&path)?.dev();
+    let (trash_dir, info_path) = match home_trash() {
+        Some(home_trash) if device_of_nearest(&home_trash)? == dev => (home_trash, path.clone()),
+        _ => {
+            let top_dir = top_dir(&path, dev)?;
+            let info_path = path.strip_prefix(&top_dir).unwrap().to_path_buf();
+            (volume_trash(&top_dir)?, info_path)
+        }
+    };
+
+    let files_dir = trash_dir.join("files");
+    let info_dir = trash_dir.join("info");
+    for dir in [&files_dir, &info_dir].iter() {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "can't trash this path"))?;
+    let (name, mut info_file) = create_info_file(&files_dir, &info_dir, file_name)?;
+    let info_file_path = info_dir.join(info_name(&name));
+    let written = write!(
+        info_file,
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(&info_path),
+        deletion_date()
+    );
+    if let Err(e) = written.and_then(|_| fs::rename(&path, files_dir.join(&name))) {
+        let _ = fs::remove_file(info_file_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    // Only the parent is canonicalized, since the entry itself may be a symlink
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok(fs::canonicalize(parent)?.join(name)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can't trash this path",
+        )),
+    }
+}
+
+/// The trash directory in `$XDG_DATA_HOME`, which might not exist yet.
+fn home_trash() -> Option<PathBuf> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(data_home) if !data_home.is_empty() => Some(PathBuf::from(data_home).join("Trash")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share/Trash")),
+    }
+}
+
+/// The device of `path`, or of its closest existing ancestor.
+fn device_of_nearest(path: &Path) -> io::Result<u64> {
+    let mut path = path;
+    loop {
+        match fs::metadata(path) {
+            Ok(metadata) => return Ok(metadata.dev()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => match path.parent() {
+                Some(parent) => path = parent,
+                None => return Err(e),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The mount point containing `path`, which is on device `dev`.
+fn top_dir(path: &Path, dev: u64) -> io::Result<PathBuf> {
+    let mut top_dir = path.parent().unwrap_or(path);
+    while let Some(parent) = top_dir.parent() {
+        if fs::metadata(parent)?.dev() != dev {
+            break;
+        }
+        top_dir = parent;
+    }
+    Ok(top_dir.to_path_buf())
+}
+
+/// The trash directory to use for files under the mount point `top_dir`, preferring a shared
+/// `$topdir/.Trash` set up by an administrator and falling back to `$topdir/.Trash-$uid`.
+fn volume_trash(top_dir: &Path) -> io::Result<PathBuf> {
+    let uid = unsafe { libc::getuid() };
+    let shared = top_dir.join(".Trash");
+    if let Ok(metadata) = fs::symlink_metadata(&shared) {
+        // The spec requires the sticky bit, and forbids following a symlink here
+        if metadata.is_dir() && metadata.permissions().mode() & 0o1000 != 0 {
+            let user_trash = shared.join(uid.to_string());
+            match DirBuilder::new().mode(0o700).create(&user_trash) {
+                Ok(()) => return Ok(user_trash),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(user_trash),
+                Err(_) => {}
+            }
+        }
+    }
+    let user_trash = top_dir.join(format!(".Trash-{}", uid));
+    match DirBuilder::new().mode(0o700).create(&user_trash) {
+        Ok(()) => Ok(user_trash),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(user_trash),
+        Err(e) => Err(e),
+    }
+}
+
+fn info_name(name: &OsString) -> OsString {
+    let mut info_name = name.clone();
+    info_name.push(".trashinfo");
+    info_name
+}
+
+/// Atomically claims a name in the trash by creating its `.trashinfo` file, adding a numeric
+/// suffix to `file_name` if it's already taken in either `files_dir` or `info_dir`.
+fn create_info_file(
+    files_dir: &Path,
+    info_dir: &Path,
+    file_name: &OsStr,
+) -> io::Result<(OsString, fs::File)> {
+    for i in 0.. {
+        let mut name = file_name.to_os_string();
+        if i > 0 {
+            name.push(format!(".{}", i));
+        }
+        // A file left without its info file, by a crash or another tool, would be replaced
+        if fs::symlink_metadata(files_dir.join(&name)).is_ok() {
+            continue;
+        }
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(info_dir.join(info_name(&name)))
+        {
+            Ok(file) => return Ok((name, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+/// Escapes `path` as required for the `Path` key, like a URL path.
+fn percent_encode(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The current local time in the `YYYY-MM-DDThh:mm:ss` format used by `DeletionDate`.
+fn deletion_date() -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        libc::localtime_r(&now, &mut tm);
+    }
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
@@ -0,0 +1,231 @@
+//! Watching scanned folders for changes with inotify, so that the tree can be kept up to date.
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    io,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// A change to an entry in a watched folder.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    /// The entry was created or moved into the folder.
+    Created,
+    /// The entry was deleted or moved out of the folder.
+    Removed,
+    /// The entry's contents were written to.
+    Modified,
+}
+
+/// Something that happened to the watched folders.
+pub enum Event {
+    /// The entry `name` in the folder at `dir`, relative to the starting directory, changed.
+    Changed {
+        dir: Vec<OsString>,
+        name: OsString,
+        change: Change,
+    },
+    /// The kernel's event queue overflowed, so changes were missed.
+    Overflowed,
+}
+
+/// Not exported by the libc crate: don't report events for entries after they're unlinked.
+#[cfg(target_os = "linux")]
+const IN_EXCL_UNLINK: u32 = 0x0400_0000;
+
+#[cfg(target_os = "linux")]
+const MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_MODIFY
+    | libc::IN_ONLYDIR
+    | libc::IN_DONT_FOLLOW
+    | IN_EXCL_UNLINK;
+
+/// An inotify instance watching folders below `root`.
+pub struct Watcher {
+    fd: i32,
+    root: PathBuf,
+    /// The folder each watch descriptor is for, relative to `root`.
+    paths: Mutex<HashMap<i32, Vec<OsString>>>,
+    /// Folders that couldn't be watched, or whose watches were lost, with the reason why.
+    unwatched: Mutex<BTreeMap<Vec<OsString>, String>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    pub fn new(root: PathBuf) -> io::Result<Watcher> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Watcher {
+            fd,
+            root,
+            paths: Mutex::new(HashMap::new()),
+            unwatched: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Starts watching each of `folders`, given relative to the root. Once the limit on the
+    /// number of watches is reached, the remaining folders are recorded as unwatched instead.
+    pub fn watch(&self, folders: Vec<Vec<OsString>>) {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let mut paths = self.paths.lock().unwrap();
+        let mut unwatched = self.unwatched.lock().unwrap();
+        let mut limit_reached = false;
+        for folder in folders {
+            unwatched.remove(&folder);
+            if limit_reached {
+                unwatched.insert(folder, "the inotify watch limit was reached".to_string());
+                continue;
+            }
+            let path = crate::join_path_to_vec(&self.root, folder.clone());
+            let c_path = match CString::new(path.as_os_str().as_bytes()) {
+                Ok(c_path) => c_path,
+                Err(e) => {
+                    unwatched.insert(folder, e.to_string());
+                    continue;
+                }
+            };
+            let wd = unsafe { libc::inotify_add_watch(self.fd, c_path.as_ptr(), MASK) };
+            if wd >= 0 {
+                paths.insert(wd, folder);
+                continue;
+            }
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(libc::ENOSPC) => {
+                    limit_reached = true;
+                    unwatched.insert(folder, "the inotify watch limit was reached".to_string());
+                }
+                // The folder was removed since it was scanned, which an event will report
+                Some(libc::ENOENT) | Some(libc::ENOTDIR) => {}
+                _ => {
+                    unwatched.insert(folder, error.to_string());
+                }
+            }
+        }
+    }
+
+    /// Stops watching the folder at `folder` and everything below it.
+    pub fn unwatch(&self, folder: &[OsString]) {
+        self.paths.lock().unwrap().retain(|wd, path| {
+            if path.starts_with(folder) {
+                unsafe { libc::inotify_rm_watch(self.fd, *wd) };
+                false
+            } else {
+                true
+            }
+        });
+        self.unwatched
+            .lock()
+            .unwrap()
+            .retain(|path, _| !path.starts_with(folder));
+    }
+
+    /// Blocks until something happens to the watched folders.
+    pub fn read_events(&self) -> io::Result<Vec<Event>> {
+        use std::{mem, os::unix::ffi::OsStringExt};
+
+        // Enough for many events at once, since each name is at most 255 bytes
+        let mut buffer = vec![0u8; 64 * 1024];
+        let read = unsafe { libc::read(self.fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let buffer = &buffer[..read as usize];
+        let header = mem::size_of::<libc::inotify_event>();
+        let mut events = vec![];
+        let mut offset = 0;
+        let mut paths = self.paths.lock().unwrap();
+        while offset + header <= buffer.len() {
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+            let name_bytes = &buffer[offset + header..offset + header + event.len as usize];
+            offset += header + event.len as usize;
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                events.push(Event::Overflowed);
+                continue;
+            }
+            if event.mask & libc::IN_IGNORED != 0 {
+                // The folder was deleted, or its filesystem unmounted
+                paths.remove(&event.wd);
+                continue;
+            }
+            let dir = match paths.get(&event.wd) {
+                Some(dir) => dir.clone(),
+                None => continue,
+            };
+            // The name is padded with nul bytes
+            let name_len = name_bytes
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(name_bytes.len());
+            let name = OsString::from_vec(name_bytes[..name_len].to_vec());
+            let change = if event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                Change::Created
+            } else if event.mask & (libc::IN_DELETE | libc::IN_MOVED_FROM) != 0 {
+                Change::Removed
+            } else {
+                Change::Modified
+            };
+            events.push(Event::Changed { dir, name, change });
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    pub fn new(_root: PathBuf) -> io::Result<Watcher> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "watching requires inotify",
+        ))
+    }
+
+    pub fn watch(&self, _folders: Vec<Vec<OsString>>) {}
+
+    pub fn unwatch(&self, _folder: &[OsString]) {}
+
+    pub fn read_events(&self) -> io::Result<Vec<Event>> {
+        unreachable!()
+    }
+}
+
+impl Watcher {
+    /// Records that changes below `folder` may have been missed.
+    pub fn mark_unwatched(&self, folder: Vec<OsString>, reason: &str) {
+        self.unwatched
+            .lock()
+            .unwrap()
+            .insert(folder, reason.to_string());
+    }
+
+    /// The folders that aren't being watched, along with why. Only the outermost folder of each
+    /// unwatched subtree is included.
+    pub fn unwatched(&self) -> Vec<(Vec<OsString>, String)> {
+        let unwatched = self.unwatched.lock().unwrap();
+        let mut outermost: Vec<(Vec<OsString>, String)> = vec![];
+        for (path, reason) in unwatched.iter() {
+            if !outermost
+                .last()
+                .is_some_and(|(last, _)| path.starts_with(last))
+            {
+                outermost.push((path.clone(), reason.clone()));
+            }
+        }
+        outermost
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}